@@ -0,0 +1,86 @@
+// An A-buffer: a list of the translucent fragments drawn to each pixel,
+// kept until the frame is resolved and then sorted and blended back to
+// front. Unlike sorting triangles before drawing them, this gets surfaces
+// that intersect or overlap cyclically right.
+
+use std::cmp::Ordering::Equal;
+
+use format::PixelFormat;
+use pixel::BlendMode;
+use pixel::Rgba;
+use types::*;
+
+
+#[derive(Clone, Copy, Debug)]
+pub struct Fragment {
+    pub color: Rgba,
+    pub depth: Coord,
+    pub blend_mode: BlendMode,
+}
+
+pub struct ABuffer {
+    pub w: Dimension,
+    pub h: Dimension,
+    // Beyond this many fragments in a pixel, the farthest are dropped.
+    pub max_fragments: usize,
+    lists: Vec<Vec<Fragment>>,
+}
+
+impl ABuffer {
+    pub fn new(w: Dimension, h: Dimension, max_fragments: usize) -> ABuffer {
+        assert!(max_fragments > 0, "an A-buffer needs room for a fragment");
+        ABuffer {
+            w,
+            h,
+            max_fragments,
+            lists: vec![Vec::new(); w as usize * h as usize],
+        }
+    }
+
+    pub fn fragments(&self, x: Dimension, y: Dimension) -> &[Fragment] {
+        &self.lists[y as usize * self.w as usize + x as usize]
+    }
+
+    pub fn insert(&mut self, x: PixCoord, y: PixCoord, fragment: Fragment) {
+        let max_fragments = self.max_fragments;
+        let list = &mut self.lists[y as usize * self.w as usize + x as usize];
+        if list.len() < max_fragments {
+            list.push(fragment);
+            return;
+        }
+
+        let farthest = (0 .. list.len())
+            .max_by(|&a, &b| {
+                list[a].depth.partial_cmp(&list[b].depth).unwrap_or(Equal)
+            })
+            .unwrap();
+        if fragment.depth < list[farthest].depth { list[farthest] = fragment }
+    }
+
+    pub fn clear(&mut self) {
+        for list in &mut self.lists { list.clear() }
+    }
+
+    // Blends each pixel's fragments in front of `depths` over `pixels`, the
+    // farthest first, then clears the lists.
+    pub fn resolve<P: PixelFormat>(
+        &mut self,
+        pixels: &mut [P],
+        depths: &[Coord],
+    ) {
+        assert!(pixels.len() == self.lists.len());
+        assert!(depths.len() == self.lists.len());
+        for (i, list) in self.lists.iter_mut().enumerate() {
+            if list.is_empty() { continue }
+            list.sort_by(|a, b| {
+                b.depth.partial_cmp(&a.depth).unwrap_or(Equal)
+            });
+            let mut color = pixels[i].to_rgba();
+            for f in list.iter().filter(|f| f.depth < depths[i]) {
+                color = f.blend_mode.blend_rgba(f.color, color);
+            }
+            pixels[i] = P::from_rgba(color);
+            list.clear();
+        }
+    }
+}
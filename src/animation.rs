@@ -0,0 +1,277 @@
+// Keyframe animation of an Object's translation, rotation and scale. A Clip
+// has a track of keyframes for each, any of which may be left out, and is
+// sampled at a time into a Pose that can be applied to an Object.
+
+use std::ops;
+
+use object::Object;
+use types::*;
+use utils::clamp;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    // Holds each keyframe's value until the next one.
+    Step,
+    Linear,
+    // A cubic through the keyframes, with tangents from their neighbors.
+    CatmullRom,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlayMode {
+    // Holds the first and last poses outside the clip.
+    Once,
+    Loop,
+    // Plays forward, then backward, and so on.
+    PingPong,
+}
+
+impl PlayMode {
+    // Maps a time onto one within a clip of the given duration.
+    pub fn local_time(&self, time: Coord, duration: Coord) -> Coord {
+        if duration <= 0. { return 0. }
+        match *self {
+            PlayMode::Once => clamp(time, 0., duration),
+            PlayMode::Loop => time.rem_euclid(duration),
+            PlayMode::PingPong => {
+                let t = time.rem_euclid(2. * duration);
+                if t > duration { 2. * duration - t } else { t }
+            },
+        }
+    }
+}
+
+
+// Values that a track can interpolate between.
+pub trait Animatable:
+    Copy + ops::Add<Output = Self> + ops::Mul<Coord, Output = Self>
+{
+    fn interpolate(a: Self, b: Self, t: Coord) -> Self;
+
+    // Picks whichever representation of this value is nearest `reference`,
+    // for types like Quaternion where more than one means the same thing.
+    fn align(self, _reference: Self) -> Self { self }
+
+    // Cleans up a value built by adding and scaling others.
+    fn normalize(self) -> Self { self }
+}
+
+impl Animatable for Point {
+    fn interpolate(a: Point, b: Point, t: Coord) -> Point {
+        a * (1. - t) + b * t
+    }
+}
+
+impl Animatable for Quaternion {
+    fn interpolate(a: Quaternion, b: Quaternion, t: Coord) -> Quaternion {
+        a.slerp(b, t)
+    }
+
+    fn align(self, reference: Quaternion) -> Quaternion {
+        if self.dot(reference) < 0. { -self } else { self }
+    }
+
+    fn normalize(self) -> Quaternion { self.normalized() }
+}
+
+
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe<T> {
+    pub time: Coord,
+    pub value: T,
+}
+
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    // Sorted by time.
+    keyframes: Vec<Keyframe<T>>,
+    pub interpolation: Interpolation,
+}
+
+impl<T: Animatable> Track<T> {
+    pub fn new(interpolation: Interpolation) -> Track<T> {
+        Track { keyframes: Vec::new(), interpolation }
+    }
+
+    pub fn from_keyframes(
+        interpolation: Interpolation,
+        keyframes: &[(Coord, T)],
+    ) -> Track<T> {
+        let mut track = Track::new(interpolation);
+        for &(time, value) in keyframes { track.add_keyframe(time, value) }
+        track
+    }
+
+    // Adds a keyframe, after any others at the same time.
+    pub fn add_keyframe(&mut self, time: Coord, value: T) {
+        let i = self.keyframes.iter()
+            .position(|k| k.time > time)
+            .unwrap_or(self.keyframes.len());
+        self.keyframes.insert(i, Keyframe { time, value });
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe<T>] { &self.keyframes }
+
+    // The time of the last keyframe.
+    pub fn duration(&self) -> Coord {
+        self.keyframes.last().map_or(0., |k| k.time)
+    }
+
+    // Returns None if the track has no keyframes. Outside the keyframes, the
+    // nearest one's value is held.
+    pub fn sample(&self, time: Coord) -> Option<T> {
+        let keys = &self.keyframes;
+        let (first, last) = match (keys.first(), keys.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return None,
+        };
+        if time <= first.time { return Some(first.value) }
+        if time >= last.time { return Some(last.value) }
+
+        let i = keys.iter().rposition(|k| k.time <= time).unwrap();
+        let (k1, k2) = (keys[i], keys[i + 1]);
+        let span = k2.time - k1.time;
+        if span <= 0. { return Some(k2.value) }
+        let t = (time - k1.time) / span;
+        let p1 = k1.value;
+        let p2 = k2.value.align(p1);
+
+        Some(match self.interpolation {
+            Interpolation::Step => p1,
+            Interpolation::Linear => T::interpolate(p1, p2, t),
+            Interpolation::CatmullRom => {
+                // The neighbors on either side, or the ends repeated, each
+                // aligned with the keyframe next to it.
+                let k0 = keys[i.saturating_sub(1)];
+                let k3 = keys[(i + 2).min(keys.len() - 1)];
+                let p0 = k0.value.align(p1);
+                let p3 = k3.value.align(p2);
+                let m1 = slope(p0, k0.time, p2, k2.time) * span;
+                let m2 = slope(p1, k1.time, p3, k3.time) * span;
+                let (t2, t3) = (t * t, t * t * t);
+                (p1 * (2. * t3 - 3. * t2 + 1.)
+                    + m1 * (t3 - 2. * t2 + t)
+                    + p2 * (-2. * t3 + 3. * t2)
+                    + m2 * (t3 - t2)).normalize()
+            },
+        })
+    }
+}
+
+// The rate of change from one value to another.
+fn slope<T: Animatable>(a: T, a_time: Coord, b: T, b_time: Coord) -> T {
+    (b + a * -1.) * (1. / (b_time - a_time))
+}
+
+
+// A translation, rotation and scale, applied in the reverse of that order,
+// as Object applies them.
+#[derive(Clone, Copy, Debug)]
+pub struct Pose {
+    pub translation: Point,
+    pub rotation: Quaternion,
+    pub scale: Point,
+}
+
+impl Pose {
+    pub fn identity() -> Pose {
+        Pose {
+            translation: pt![0., 0., 0.],
+            rotation: Quaternion::identity(),
+            scale: pt![1., 1., 1.],
+        }
+    }
+
+    pub fn to_transform(&self) -> Transform {
+        Transform::translate(self.translation)
+            * self.rotation.to_transform()
+            * Transform::scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    pub fn interpolate(a: &Pose, b: &Pose, t: Coord) -> Pose {
+        Pose {
+            translation: Point::interpolate(a.translation, b.translation, t),
+            rotation: a.rotation.slerp(b.rotation, t),
+            scale: Point::interpolate(a.scale, b.scale, t),
+        }
+    }
+
+    // Mixes poses by weight. The weights needn't add up to one, but if they
+    // add up to zero the result is the identity.
+    pub fn blend(poses: &[(Pose, Coord)]) -> Pose {
+        let mut total = 0.;
+        let mut blended = Pose::identity();
+        for &(pose, weight) in poses {
+            if weight <= 0. { continue }
+            total += weight;
+            blended = Pose::interpolate(&blended, &pose, weight / total);
+        }
+        blended
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct Clip {
+    pub translation: Option<Track<Point>>,
+    pub rotation: Option<Track<Quaternion>>,
+    pub scale: Option<Track<Point>>,
+    pub mode: PlayMode,
+}
+
+impl Clip {
+    pub fn new(mode: PlayMode) -> Clip {
+        Clip { translation: None, rotation: None, scale: None, mode }
+    }
+
+    // The time of the last keyframe on any track. Clips start at time 0.
+    pub fn duration(&self) -> Coord {
+        let durations = [
+            self.translation.as_ref().map_or(0., |t| t.duration()),
+            self.rotation.as_ref().map_or(0., |t| t.duration()),
+            self.scale.as_ref().map_or(0., |t| t.duration()),
+        ];
+        durations.iter().cloned().fold(0., Coord::max)
+    }
+
+    // Samples the clip at a time, wrapped according to its play mode. Parts
+    // of the pose without a track, or with an empty one, are taken from
+    // `base`.
+    pub fn sample(&self, time: Coord, base: &Pose) -> Pose {
+        let time = self.mode.local_time(time, self.duration());
+        self.sample_local(time, base)
+    }
+
+    // Like sample, but without wrapping the time.
+    pub fn sample_local(&self, time: Coord, base: &Pose) -> Pose {
+        Pose {
+            translation: sample_or(&self.translation, time, base.translation),
+            rotation: sample_or(&self.rotation, time, base.rotation),
+            scale: sample_or(&self.scale, time, base.scale),
+        }
+    }
+
+    // Poses an object as the clip has it at a time.
+    pub fn apply(&self, time: Coord, object: &mut Object) {
+        let pose = self.sample(time, &object.pose());
+        object.set_pose(&pose);
+    }
+}
+
+fn sample_or<T: Animatable>(track: &Option<Track<T>>, time: Coord, default: T)
+    -> T
+{
+    track.as_ref().and_then(|t| t.sample(time)).unwrap_or(default)
+}
+
+// Samples several clips at once and blends the results by weight, e.g. to
+// fade from one clip to another.
+pub fn blend_clips(clips: &[(&Clip, Coord)], time: Coord, base: &Pose)
+    -> Pose
+{
+    let poses: Vec<(Pose, Coord)> = clips.iter()
+        .map(|&(clip, weight)| (clip.sample(time, base), weight))
+        .collect();
+    Pose::blend(&poses)
+}
@@ -0,0 +1,307 @@
+// Antialiasing. Supersampling renders the whole frame at a multiple of the
+// screen's size and averages it down. Multisampling keeps a color and depth
+// per sample, tests each sample's coverage separately, but shades each
+// pixel only once per triangle, so it costs far less than supersampling at
+// the same number of samples.
+
+use format::PixelFormat;
+use pixel;
+use pixel::BlendMode;
+use pixel::Rgba;
+use texture::Texture;
+use types::*;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Antialiasing {
+    None,
+    // Renders at this many times the width and height, i.e. the square of
+    // it samples per pixel.
+    Supersample(Dimension),
+    // This many samples per pixel: 1, 2, 4 or 8.
+    Multisample(Dimension),
+}
+
+// Where samples sit within a pixel, with (0, 0) its top left corner. These
+// are the standard Direct3D patterns, which avoid lining samples up along
+// rows or columns.
+const SAMPLES_1: [(Coord, Coord); 1] = [(0.5, 0.5)];
+const SAMPLES_2: [(Coord, Coord); 2] = [(0.75, 0.75), (0.25, 0.25)];
+const SAMPLES_4: [(Coord, Coord); 4] = [
+    (0.375, 0.125), (0.875, 0.375), (0.125, 0.625), (0.625, 0.875),
+];
+const SAMPLES_8: [(Coord, Coord); 8] = [
+    (0.5625, 0.3125), (0.4375, 0.6875), (0.8125, 0.5625), (0.3125, 0.1875),
+    (0.1875, 0.8125), (0.0625, 0.4375), (0.6875, 0.9375), (0.9375, 0.0625),
+];
+
+pub fn sample_positions(samples: Dimension) -> &'static [(Coord, Coord)] {
+    match samples {
+        1 => &SAMPLES_1,
+        2 => &SAMPLES_2,
+        4 => &SAMPLES_4,
+        8 => &SAMPLES_8,
+        _ => panic!("{} samples per pixel is not supported; use 1, 2, 4 \
+                     or 8", samples),
+    }
+}
+
+
+// A color and depth for every sample of every pixel, stored a pixel at a
+// time.
+pub struct MultisampleBuffer {
+    pub w: Dimension,
+    pub h: Dimension,
+    positions: &'static [(Coord, Coord)],
+    colors: Vec<Rgba>,
+    depths: Vec<Coord>,
+
+    // As for Texture.
+    blend_mode: BlendMode,
+    depth_write: bool,
+}
+
+impl MultisampleBuffer {
+    pub fn new(w: Dimension, h: Dimension, samples: Dimension)
+        -> MultisampleBuffer
+    {
+        let positions = sample_positions(samples);
+        let num_samples = w as usize * h as usize * positions.len();
+        MultisampleBuffer {
+            w,
+            h,
+            positions,
+            colors: vec![Rgba::from(pixel::BLACK); num_samples],
+            depths: vec![Coord::INFINITY; num_samples],
+
+            blend_mode: BlendMode::Replace,
+            depth_write: true,
+        }
+    }
+
+    pub fn samples(&self) -> usize { self.positions.len() }
+
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+    }
+
+    pub fn set_depth_write(&mut self, enabled: bool) {
+        self.depth_write = enabled;
+    }
+
+    pub fn clear_to(&mut self, color: Rgba) {
+        for i in 0 .. self.colors.len() {
+            self.colors[i] = color;
+            self.depths[i] = Coord::INFINITY;
+        }
+    }
+
+    fn first_sample(&self, x: PixCoord, y: PixCoord) -> usize {
+        (y as usize * self.w as usize + x as usize) * self.samples()
+    }
+
+    // Blends in one sample if it passes the depth test, and says whether
+    // it did.
+    fn set_sample(&mut self, index: usize, z: Coord, color: Rgba) -> bool {
+        if z >= self.depths[index] { return false }
+        self.colors[index] =
+            self.blend_mode.blend_rgba(color, self.colors[index]);
+        if self.depth_write { self.depths[index] = z }
+        true
+    }
+
+    // Writes every sample of a pixel, for things like lines and points that
+    // aren't antialiased, and says whether any passed the depth test.
+    pub fn set_pixel(
+        &mut self,
+        x: PixCoord,
+        y: PixCoord,
+        z: Coord,
+        color: Rgba
+    ) -> bool {
+        if x < 0 || y < 0 { return false }
+        if x as Dimension >= self.w || y as Dimension >= self.h {
+            return false;
+        }
+        let first = self.first_sample(x, y);
+        let mut written = false;
+        for i in first .. first + self.samples() {
+            written |= self.set_sample(i, z, color);
+        }
+        written
+    }
+
+    // The depth of the nearest sample in a pixel.
+    fn nearest(&self, x: PixCoord, y: PixCoord) -> Coord {
+        let first = self.first_sample(x, y);
+        self.depths[first .. first + self.samples()].iter()
+            .cloned()
+            .fold(Coord::INFINITY, Coord::min)
+    }
+
+    // Fills a triangle given in screen x and y, with depth in z. `shade` is
+    // asked for a pixel's color once, given the depth at its center, when
+    // any of its samples are covered. With depth writes on, `on_nearest` is
+    // told of each pixel where the triangle now holds the nearest sample,
+    // e.g. to update a g-buffer.
+    pub fn fill_triangle<F, G>(
+        &mut self,
+        pts: [Point; 3],
+        shade: F,
+        mut on_nearest: G,
+    )
+        where F: Fn(Coord) -> Rgba,
+              G: FnMut(PixCoord, PixCoord)
+    {
+        let [a, b, c] = pts;
+        let area = edge(a, b, c.x, c.y);
+        if area == 0. || !area.is_finite() { return }
+
+        // Barycentric weights of a, b and c at a point, all positive inside.
+        let weights = |x: Coord, y: Coord| {
+            (edge(b, c, x, y) / area, edge(c, a, x, y) / area,
+             edge(a, b, x, y) / area)
+        };
+        // Samples exactly on an edge belong to it only if it's a top or left
+        // edge, so triangles sharing an edge don't both cover them.
+        let owns = (top_left(b, c, area), top_left(c, a, area),
+                    top_left(a, b, area));
+        let covers = |w: Coord, owned: bool| w > 0. || w == 0. && owned;
+        let depth_at = |(wa, wb, wc): (Coord, Coord, Coord)| {
+            wa * a.z + wb * b.z + wc * c.z
+        };
+
+        let max_x = self.w as Coord - 1.;
+        let max_y = self.h as Coord - 1.;
+        let x_min = a.x.min(b.x).min(c.x).floor().max(0.);
+        let x_max = a.x.max(b.x).max(c.x).ceil().min(max_x);
+        let y_min = a.y.min(b.y).min(c.y).floor().max(0.);
+        let y_max = a.y.max(b.y).max(c.y).ceil().min(max_y);
+        if x_min > x_max || y_min > y_max { return }
+
+        for y in y_min as PixCoord .. y_max as PixCoord + 1 {
+            for x in x_min as PixCoord .. x_max as PixCoord + 1 {
+                let first = self.first_sample(x, y);
+                let mut color = None;
+                let mut nearest_written: Option<Coord> = None;
+
+                for (i, &(sx, sy)) in self.positions.iter().enumerate() {
+                    let w = weights(x as Coord + sx, y as Coord + sy);
+                    if !(covers(w.0, owns.0) && covers(w.1, owns.1)
+                         && covers(w.2, owns.2))
+                    {
+                        continue
+                    }
+
+                    let z = depth_at(w);
+                    let color = *color.get_or_insert_with(|| {
+                        let center = (x as Coord + 0.5, y as Coord + 0.5);
+                        shade(depth_at(weights(center.0, center.1)))
+                    });
+                    if self.set_sample(first + i, z, color) {
+                        nearest_written = Some(
+                            nearest_written.map_or(z, |n: Coord| n.min(z))
+                        );
+                    }
+                }
+
+                // Only pixels the triangle wrote a sample to.
+                let nearest = self.nearest(x, y);
+                if self.depth_write
+                    && nearest_written.is_some_and(|z| z <= nearest)
+                {
+                    on_nearest(x, y);
+                }
+            }
+        }
+    }
+
+    // Averages each pixel's samples into a texture the same size as the
+    // buffer, along with the nearest depth. The texture's g-buffer is left
+    // as it is.
+    pub fn resolve<P: PixelFormat>(&self, texture: &mut Texture<P>) {
+        assert!(texture.w == self.w && texture.h == self.h);
+        let n = self.samples();
+        for y in 0 .. self.h as PixCoord {
+            for x in 0 .. self.w as PixCoord {
+                let first = self.first_sample(x, y);
+                let colors = self.colors[first .. first + n].iter().cloned();
+                texture.overwrite_pixel(x, y, self.nearest(x, y),
+                                        P::from_rgba(average(colors)));
+            }
+        }
+    }
+}
+
+// Twice the signed area of the triangle a, b, (x, y).
+fn edge(a: Point, b: Point, x: Coord, y: Coord) -> Coord {
+    (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x)
+}
+
+// Whether the edge from a to b of a triangle with twice the signed area
+// `area` is a top or left one: the inside is to its right, or below it if
+// it's level.
+fn top_left(a: Point, b: Point, area: Coord) -> bool {
+    // How the edge's weight grows along x and y.
+    let (dx, dy) = ((a.y - b.y) / area, (b.x - a.x) / area);
+    dx > 0. || dx == 0. && dy > 0.
+}
+
+fn average<I: IntoIterator<Item = Rgba>>(colors: I) -> Rgba {
+    let mut count = 0;
+    let mut sum = Rgba::new(0., 0., 0., 0.);
+    for c in colors {
+        sum = Rgba::new(sum.r + c.r, sum.g + c.g, sum.b + c.b, sum.a + c.a);
+        count += 1;
+    }
+    if count == 0 { return sum }
+    let k = 1. / count as f32;
+    Rgba::new(sum.r * k, sum.g * k, sum.b * k, sum.a * k)
+}
+
+
+// Averages each `factor` by `factor` block of a supersampled texture into a
+// pixel of `dst`, keeping the nearest depth. If both have g-buffers, each
+// pixel takes its entry from the nearest sample in its block.
+pub fn downsample<P: PixelFormat>(
+    src: &Texture<P>,
+    factor: Dimension,
+    dst: &mut Texture<P>,
+) {
+    assert!(src.w == dst.w * factor && src.h == dst.h * factor);
+    let depths = src.depth();
+    let f = factor as usize;
+
+    for y in 0 .. dst.h as usize {
+        for x in 0 .. dst.w as usize {
+            let block = (y * f .. (y + 1) * f)
+                .flat_map(|sy| (x * f .. (x + 1) * f).map(move |sx| (sx, sy)));
+
+            let mut nearest = None;
+            let mut nearest_depth = Coord::INFINITY;
+            for (sx, sy) in block.clone() {
+                let z = depths.get(sx as Dimension, sy as Dimension);
+                if z < nearest_depth {
+                    nearest = Some(sy * src.w as usize + sx);
+                    nearest_depth = z;
+                }
+            }
+
+            let color = average(block.map(|(sx, sy)| {
+                src.pixels[sy * src.w as usize + sx].to_rgba()
+            }));
+            dst.overwrite_pixel(x as PixCoord, y as PixCoord, nearest_depth,
+                                P::from_rgba(color));
+
+            if let (Some(from), Some(to), Some(i)) =
+                (src.gbuffer.as_ref(), dst.gbuffer.as_mut(), nearest)
+            {
+                let j = y * dst.w as usize + x;
+                to.normals[j]          = from.normals[i];
+                to.positions[j]        = from.positions[i];
+                to.object_ids[j]       = from.object_ids[i];
+                to.triangle_indices[j] = from.triangle_indices[i];
+            }
+        }
+    }
+}
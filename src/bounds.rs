@@ -0,0 +1,205 @@
+
+use types::*;
+
+
+// An axis-aligned bounding box. An empty box has min > max.
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Point,
+    pub max: Point,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Sphere {
+    pub center: Point,
+    pub radius: Coord,
+}
+
+// A plane with its normal pointing to the inside half-space, where
+// normal.dot(p) + d >= 0.
+#[derive(Clone, Copy, Debug)]
+pub struct Plane {
+    pub normal: Point,
+    pub d: Coord,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    pub planes: [Plane; 5],
+}
+
+impl Aabb {
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: pt![Coord::INFINITY, Coord::INFINITY, Coord::INFINITY],
+            max: pt![
+                Coord::NEG_INFINITY,
+                Coord::NEG_INFINITY,
+                Coord::NEG_INFINITY
+            ],
+        }
+    }
+
+    pub fn from_points<I>(points: I) -> Aabb
+        where I: IntoIterator<Item = Point>
+    {
+        points.into_iter().fold(Aabb::empty(), |b, p| b.including(p))
+    }
+
+    pub fn from_triangles(tris: &[Triangle]) -> Aabb {
+        Aabb::from_points(tris.iter().flat_map(|t| t.to_arr().to_vec()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x ||
+        self.min.y > self.max.y ||
+        self.min.z > self.max.z
+    }
+
+    pub fn including(self, p: Point) -> Aabb {
+        let (lo, hi) = (self.min, self.max);
+        Aabb {
+            min: pt![lo.x.min(p.x), lo.y.min(p.y), lo.z.min(p.z)],
+            max: pt![hi.x.max(p.x), hi.y.max(p.y), hi.z.max(p.z)],
+        }
+    }
+
+    // Component-wise, so that an empty box adds nothing.
+    pub fn union(self, other: Aabb) -> Aabb {
+        let (a, b) = (self, other);
+        Aabb {
+            min: pt![a.min.x.min(b.min.x), a.min.y.min(b.min.y),
+                     a.min.z.min(b.min.z)],
+            max: pt![a.max.x.max(b.max.x), a.max.y.max(b.max.y),
+                     a.max.z.max(b.max.z)],
+        }
+    }
+
+    pub fn center(&self) -> Point {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extent(&self) -> Point {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> Coord {
+        if self.is_empty() { return 0. }
+        let e = self.extent();
+        2. * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+
+    // Slab test. Returns the distances along the ray at which it enters and
+    // leaves the box, if it hits at or after the ray's origin.
+    pub fn intersect_ray(&self, ray: Ray) -> Option<(Coord, Coord)> {
+        let axes = [
+            (ray.origin.x, ray.direction.x, self.min.x, self.max.x),
+            (ray.origin.y, ray.direction.y, self.min.y, self.max.y),
+            (ray.origin.z, ray.direction.z, self.min.z, self.max.z),
+        ];
+        let mut t_enter: Coord = 0.;
+        let mut t_exit = Coord::INFINITY;
+        for &(origin, direction, lo, hi) in &axes {
+            let inv = 1. / direction;
+            let (t1, t2) = ((lo - origin) * inv, (hi - origin) * inv);
+            // NaN from 0 * infinity on a slab's face counts as inside.
+            t_enter = t_enter.max(t1.min(t2));
+            t_exit  = t_exit.min(t1.max(t2));
+        }
+        if t_enter <= t_exit { Some((t_enter, t_exit)) } else { None }
+    }
+
+    pub fn corners(&self) -> [Point; 8] {
+        let (lo, hi) = (self.min, self.max);
+        [
+            pt![lo.x, lo.y, lo.z], pt![hi.x, lo.y, lo.z],
+            pt![lo.x, hi.y, lo.z], pt![hi.x, hi.y, lo.z],
+            pt![lo.x, lo.y, hi.z], pt![hi.x, lo.y, hi.z],
+            pt![lo.x, hi.y, hi.z], pt![hi.x, hi.y, hi.z],
+        ]
+    }
+
+    // Returns a box around this one after transformation. It may be looser
+    // than a box fit to the transformed contents.
+    pub fn transformed(&self, t: Transform) -> Aabb {
+        if self.is_empty() { return *self }
+        Aabb::from_points(self.corners().iter().map(|&p| p * t))
+    }
+
+    // Returns the smallest sphere containing this box.
+    pub fn bounding_sphere(&self) -> Sphere {
+        if self.is_empty() {
+            return Sphere { center: pt![0., 0., 0.], radius: -1. };
+        }
+        let center = self.center();
+        Sphere { center, radius: (self.max - center).magnitude() }
+    }
+}
+
+impl Plane {
+    // With a zero normal, as for the near plane of a transform without
+    // perspective, the plane is left as d >= 0, which either everything or
+    // nothing is inside.
+    pub fn new(a: Coord, b: Coord, c: Coord, d: Coord) -> Plane {
+        let normal = pt![a, b, c];
+        let len = normal.magnitude();
+        if len == 0. { return Plane { normal, d } }
+        Plane { normal: normal * (1. / len), d: d / len }
+    }
+
+    pub fn distance(&self, p: Point) -> Coord {
+        self.normal.dot(p) + self.d
+    }
+}
+
+impl Frustum {
+    // Extracts the view frustum from a transform mapping view space onto a
+    // w by h screen, clipping everything nearer than `near` to the eye.
+    pub fn from_screen_transform(
+        t: Transform,
+        w: Dimension,
+        h: Dimension,
+        near: Coord,
+    ) -> Frustum {
+        let (x, y, wr) = (t.row(0), t.row(1), t.row(DIM));
+        let (w, h) = (w as Coord, h as Coord);
+
+        // Each plane is a difference of rows, a - b >= 0.
+        let scaled = |r: [Coord; DIM + 1], s: Coord| {
+            [r[0] * s, r[1] * s, r[2] * s, r[3] * s]
+        };
+        let minus = |a: [Coord; DIM + 1], b: [Coord; DIM + 1]| {
+            Plane::new(a[0] - b[0], a[1] - b[1], a[2] - b[2], a[3] - b[3])
+        };
+        let zero = [0.; DIM + 1];
+        Frustum {
+            planes: [
+                minus(x, zero),             // Left:   x >= 0.
+                minus(scaled(wr, w), x),    // Right:  x <= w.
+                minus(y, zero),             // Top:    y >= 0.
+                minus(scaled(wr, h), y),    // Bottom: y <= h.
+                minus(wr, [0., 0., 0., near]), // Near: w >= near.
+            ],
+        }
+    }
+
+    // Returns false only if the sphere is entirely outside.
+    pub fn intersects_sphere(&self, s: &Sphere) -> bool {
+        s.radius >= 0. &&
+            self.planes.iter().all(|p| p.distance(s.center) >= -s.radius)
+    }
+
+    // Returns false only if the box is entirely outside.
+    pub fn intersects_aabb(&self, b: &Aabb) -> bool {
+        if b.is_empty() { return false }
+        self.planes.iter().all(|p| {
+            // Test the corner farthest along the plane's normal.
+            let corner = pt![
+                if p.normal.x >= 0. { b.max.x } else { b.min.x },
+                if p.normal.y >= 0. { b.max.y } else { b.min.y },
+                if p.normal.z >= 0. { b.max.z } else { b.min.z }
+            ];
+            p.distance(corner) >= 0.
+        })
+    }
+}
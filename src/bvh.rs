@@ -0,0 +1,236 @@
+// A bounding volume hierarchy over anything with an Aabb: an object's
+// triangles, or the objects in a scene.
+
+
+use bounds::Aabb;
+use types::*;
+
+
+const NUM_BINS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+
+// Relative cost of testing a node's box against testing one of its items.
+const TRAVERSAL_COST: Coord = 1.;
+
+#[derive(Clone)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    items: Vec<usize>,
+}
+
+#[derive(Clone, Copy)]
+struct BvhNode {
+    bounds: Aabb,
+    // Leaves own items[first .. first + count]. Interior nodes have a count
+    // of 0 and children at nodes[first] and nodes[first + 1].
+    first: usize,
+    count: usize,
+}
+
+impl Bvh {
+    // Builds a hierarchy over items with the given bounds, splitting nodes by
+    // binned surface area heuristic.
+    pub fn build(bounds: &[Aabb]) -> Bvh {
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * bounds.len()),
+            items: (0 .. bounds.len()).collect(),
+        };
+        let centroids: Vec<Point> = bounds.iter().map(|b| b.center()).collect();
+
+        bvh.nodes.push(BvhNode {
+            bounds: Aabb::empty(),
+            first: 0,
+            count: bounds.len(),
+        });
+        bvh.subdivide(0, bounds, &centroids);
+        bvh
+    }
+
+    pub fn bounds(&self) -> Aabb { self.nodes[0].bounds }
+    pub fn len(&self) -> usize { self.items.len() }
+    pub fn is_empty(&self) -> bool { self.items.is_empty() }
+
+    fn subdivide(
+        &mut self,
+        node: usize,
+        bounds: &[Aabb],
+        centroids: &[Point],
+    ) {
+        let BvhNode { first, count, .. } = self.nodes[node];
+        let items = &mut self.items[first .. first + count];
+        self.nodes[node].bounds = items.iter()
+            .fold(Aabb::empty(), |b, &i| b.union(bounds[i]));
+        if count <= 1 { return }
+
+        let split = match best_split(items, bounds, centroids) {
+            Some((axis, position, cost)) => {
+                let leaf_cost = count as Coord;
+                if cost >= leaf_cost && count <= MAX_LEAF_SIZE { return }
+                partition(items, |i| axis_of(centroids[i], axis) < position)
+            },
+            None => {
+                // Every centroid is in the same place. Split down the middle.
+                if count <= MAX_LEAF_SIZE { return }
+                count / 2
+            },
+        };
+        // Never leave a side empty.
+        let split =
+            if split == 0 || split == count { count / 2 } else { split };
+
+        let left = self.nodes.len();
+        self.nodes[node].first = left;
+        self.nodes[node].count = 0;
+        self.nodes.push(BvhNode { bounds: Aabb::empty(), first, count: split });
+        self.nodes.push(BvhNode {
+            bounds: Aabb::empty(),
+            first: first + split,
+            count: count - split,
+        });
+        self.subdivide(left,     bounds, centroids);
+        self.subdivide(left + 1, bounds, centroids);
+    }
+
+    // Updates node bounds for items that have moved, keeping the tree's
+    // shape. Cheaper than a rebuild, but gets less efficient as the items
+    // move further from where they were built.
+    pub fn refit(&mut self, bounds: &[Aabb]) {
+        assert!(bounds.len() == self.items.len());
+        // Children always come after their parents.
+        for n in (0 .. self.nodes.len()).rev() {
+            let BvhNode { first, count, .. } = self.nodes[n];
+            self.nodes[n].bounds = if count > 0 {
+                self.items[first .. first + count].iter()
+                    .fold(Aabb::empty(), |b, &i| b.union(bounds[i]))
+            } else {
+                self.nodes[first].bounds.union(self.nodes[first + 1].bounds)
+            };
+        }
+    }
+
+    // Calls `visit` with each item whose ancestors' bounds all pass `test`.
+    // Items themselves are not tested.
+    pub fn visit<T, V>(&self, mut test: T, mut visit: V)
+        where T: FnMut(&Aabb) -> bool,
+              V: FnMut(usize)
+    {
+        if self.is_empty() { return }
+        let mut stack = vec![0];
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            if !test(&node.bounds) { continue }
+            if node.count > 0 {
+                for &i in &self.items[node.first .. node.first + node.count] {
+                    visit(i);
+                }
+            } else {
+                stack.push(node.first);
+                stack.push(node.first + 1);
+            }
+        }
+    }
+
+    // Finds the nearest item along a ray. `hit` returns the distance along
+    // the ray at which it hits an item, if it does.
+    pub fn intersect_ray<H>(&self, ray: Ray, mut hit: H)
+        -> Option<(usize, Coord)>
+        where H: FnMut(usize) -> Option<Coord>
+    {
+        if self.is_empty() { return None }
+        let mut nearest: Option<(usize, Coord)> = None;
+        let mut stack = vec![0];
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            let t_max = nearest.map_or(Coord::INFINITY, |(_, t)| t);
+            match node.bounds.intersect_ray(ray) {
+                Some((t_enter, _)) if t_enter <= t_max => {},
+                _ => continue,
+            }
+
+            if node.count > 0 {
+                for &i in &self.items[node.first .. node.first + node.count] {
+                    if let Some(t) = hit(i) {
+                        let best = nearest.map_or(Coord::INFINITY, |(_, b)| b);
+                        if t < best { nearest = Some((i, t)) }
+                    }
+                }
+            } else {
+                stack.push(node.first);
+                stack.push(node.first + 1);
+            }
+        }
+        nearest
+    }
+}
+
+fn axis_of(p: Point, axis: usize) -> Coord {
+    match axis {
+        0 => p.x,
+        1 => p.y,
+        _ => p.z,
+    }
+}
+
+// Returns the axis and position of the cheapest binned split, with its
+// estimated cost relative to testing one item.
+fn best_split(items: &[usize], bounds: &[Aabb], centroids: &[Point])
+    -> Option<(usize, Coord, Coord)>
+{
+    let centroid_bounds =
+        Aabb::from_points(items.iter().map(|&i| centroids[i]));
+    let parent_area = items.iter()
+        .fold(Aabb::empty(), |b, &i| b.union(bounds[i]))
+        .surface_area();
+
+    let mut best: Option<(usize, Coord, Coord)> = None;
+    for axis in 0 .. DIM {
+        let lo = axis_of(centroid_bounds.min, axis);
+        let hi = axis_of(centroid_bounds.max, axis);
+        if hi <= lo { continue }
+
+        let mut bins = [(Aabb::empty(), 0usize); NUM_BINS];
+        let scale = NUM_BINS as Coord / (hi - lo);
+        for &i in items {
+            let b = ((axis_of(centroids[i], axis) - lo) * scale) as usize;
+            let bin = &mut bins[b.min(NUM_BINS - 1)];
+            bin.0 = bin.0.union(bounds[i]);
+            bin.1 += 1;
+        }
+
+        // Sweep from the right, then from the left, to cost each boundary.
+        let mut right_costs = [0.; NUM_BINS];
+        let (mut right_box, mut right_count) = (Aabb::empty(), 0);
+        for b in (1 .. NUM_BINS).rev() {
+            right_box = right_box.union(bins[b].0);
+            right_count += bins[b].1;
+            right_costs[b] = right_box.surface_area() * right_count as Coord;
+        }
+        let (mut left_box, mut left_count) = (Aabb::empty(), 0);
+        for b in 1 .. NUM_BINS {
+            left_box = left_box.union(bins[b - 1].0);
+            left_count += bins[b - 1].1;
+            let cost = TRAVERSAL_COST + (
+                left_box.surface_area() * left_count as Coord
+                    + right_costs[b]
+            ) / parent_area.max(Coord::MIN_POSITIVE);
+            if cost < best.map_or(Coord::INFINITY, |(_, _, c)| c) {
+                best = Some((axis, lo + b as Coord / scale, cost));
+            }
+        }
+    }
+    best
+}
+
+// Moves items for which `left` holds to the front, returning how many.
+fn partition<F>(items: &mut [usize], left: F) -> usize
+    where F: Fn(usize) -> bool
+{
+    let mut split = 0;
+    for i in 0 .. items.len() {
+        if left(items[i]) {
+            items.swap(i, split);
+            split += 1;
+        }
+    }
+    split
+}
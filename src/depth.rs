@@ -0,0 +1,114 @@
+use std::io;
+use std::io::Write;
+
+use netpbm;
+use pixel;
+use pixel::Color;
+use pixel::Pixel;
+use texture::Texture;
+use types::*;
+use utils::clamp;
+
+
+// How depth is mapped to color when visualizing a depth buffer.
+#[derive(Clone, Copy, Debug)]
+pub enum DepthRamp {
+    // Near is black, far is white.
+    Grayscale,
+    // Near is red, through yellow, green and cyan, to far in blue.
+    FalseColor,
+}
+
+// A read-only view of a depth buffer. Depths are view-space distances along
+// -z; pixels that nothing was drawn to hold infinity.
+#[derive(Clone, Copy)]
+pub struct DepthView<'a> {
+    pub w: Dimension,
+    pub h: Dimension,
+    depths: &'a [Coord],
+}
+
+impl<'a> DepthView<'a> {
+    pub fn new(w: Dimension, h: Dimension, depths: &'a [Coord])
+        -> DepthView<'a>
+    {
+        assert!(depths.len() == w as usize * h as usize);
+        DepthView { w, h, depths }
+    }
+
+    pub fn get(&self, x: Dimension, y: Dimension) -> Coord {
+        self.depths[y as usize * self.w as usize + x as usize]
+    }
+
+    pub fn as_slice(&self) -> &'a [Coord] { self.depths }
+
+    // Returns the nearest and farthest finite depths, if there are any.
+    pub fn range(&self) -> Option<(Coord, Coord)> {
+        self.depths.iter()
+            .filter(|z| z.is_finite())
+            .fold(None, |range, &z| match range {
+                None => Some((z, z)),
+                Some((near, far)) => Some((near.min(z), far.max(z))),
+            })
+    }
+
+    // Maps depth in [near, far] to [0, 1]. Empty pixels map to 1.
+    fn normalized(&self, z: Coord, near: Coord, far: Coord) -> Coord {
+        if !z.is_finite() || far <= near { return 1. }
+        clamp((z - near) / (far - near), 0., 1.)
+    }
+
+    pub fn visualize(&self, near: Coord, far: Coord, ramp: DepthRamp)
+        -> Texture
+    {
+        let mut texture = Texture::new(self.w, self.h);
+        for (i, &z) in self.depths.iter().enumerate() {
+            let t = self.normalized(z, near, far);
+            texture.pixels[i] = Color::from_srgb(match ramp {
+                DepthRamp::Grayscale =>
+                    pixel::lerp(pixel::BLACK, pixel::WHITE, t),
+                DepthRamp::FalseColor => false_color(t),
+            });
+        }
+        texture
+    }
+
+    // Writes raw depths as a single-channel PFM.
+    pub fn write_pfm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let values: Vec<f32> = self.depths.iter().map(|&z| z as f32).collect();
+        netpbm::write_pfm(out, self.w, self.h, 1, &values)
+    }
+
+    // Writes depths normalized to [near, far] as a 16-bit binary PGM.
+    pub fn write_pgm16<W: Write>(&self, out: &mut W, near: Coord, far: Coord)
+        -> io::Result<()>
+    {
+        let values: Vec<u16> = self.depths.iter()
+            .map(|&z| (self.normalized(z, near, far) * 65535.).round() as u16)
+            .collect();
+        netpbm::write_pgm16(out, self.w, self.h, &values)
+    }
+
+    pub fn save_pfm(&self, filename: &str) -> io::Result<()> {
+        self.write_pfm(&mut netpbm::create(filename)?)
+    }
+
+    pub fn save_pgm16(&self, filename: &str, near: Coord, far: Coord)
+        -> io::Result<()>
+    {
+        self.write_pgm16(&mut netpbm::create(filename)?, near, far)
+    }
+}
+
+fn false_color(t: Coord) -> Pixel {
+    const STOPS: [Pixel; 5] = [
+        pixel::RED,
+        (0xff, 0xff, 0x00),
+        pixel::GREEN,
+        (0x00, 0xff, 0xff),
+        pixel::BLUE,
+    ];
+    let scaled = t * (STOPS.len() - 1) as Coord;
+    let i = (scaled as usize).min(STOPS.len() - 2);
+    pixel::lerp(STOPS[i], STOPS[i + 1], scaled - i as Coord)
+}
@@ -0,0 +1,120 @@
+// The pixel formats a Texture can hold. Colors pass between formats as
+// linear Rgba. The 8-bit formats store sRGB-encoded color, ready for the
+// screen as it is; the float formats store linear light, which is tone
+// mapped on the way out. Single-channel formats keep luminance and read
+// back as gray.
+
+use pixel::Color;
+use pixel::Pixel;
+use pixel::Rgba;
+use pixel::linear_to_srgb;
+use pixel::srgb_to_linear;
+use tonemap::ToneMapping;
+use utils::clamp;
+
+
+pub trait PixelFormat: Copy + Send + Sync + 'static {
+    fn from_rgba(c: Rgba) -> Self;
+    fn to_rgba(self) -> Rgba;
+
+    // How the pixel looks on screen.
+    fn to_display(self, tone_mapping: &ToneMapping) -> Pixel;
+
+    fn convert<Q: PixelFormat>(self) -> Q { Q::from_rgba(self.to_rgba()) }
+}
+
+pub type Rgb8 = Pixel;
+pub type Rgbf32 = Color;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Gray8(pub u8);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rgba8 {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    // Linear, unlike the color.
+    pub a: u8,
+}
+
+// Five bits of red, six of green and five of blue, from high to low.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rgb565(pub u16);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct R32f(pub f32);
+
+
+fn gray(v: f32) -> Rgba { Rgba::new(v, v, v, 1.) }
+
+fn alpha_to_u8(a: f32) -> u8 { (clamp(a, 0., 1.) * 255.).round() as u8 }
+
+// Rescales an sRGB-encoded channel to and from fewer bits.
+fn to_bits(c: u8, bits: u32) -> u16 {
+    let max = (1 << bits) - 1;
+    ((c as u32 * max + 127) / 255) as u16
+}
+
+fn from_bits(v: u16, bits: u32) -> u8 {
+    let max = (1 << bits) - 1;
+    ((v as u32 * 255 + max / 2) / max) as u8
+}
+
+impl PixelFormat for Pixel {
+    fn from_rgba(c: Rgba) -> Pixel { c.color().to_srgb() }
+    fn to_rgba(self) -> Rgba { Rgba::from(self) }
+    fn to_display(self, _: &ToneMapping) -> Pixel { self }
+}
+
+impl PixelFormat for Color {
+    fn from_rgba(c: Rgba) -> Color { c.color() }
+    fn to_rgba(self) -> Rgba { Rgba::from(self) }
+    fn to_display(self, tone_mapping: &ToneMapping) -> Pixel {
+        tone_mapping.map(self)
+    }
+}
+
+impl PixelFormat for Gray8 {
+    fn from_rgba(c: Rgba) -> Gray8 {
+        Gray8(linear_to_srgb(c.color().luminance()))
+    }
+    fn to_rgba(self) -> Rgba { gray(srgb_to_linear(self.0)) }
+    fn to_display(self, _: &ToneMapping) -> Pixel { (self.0, self.0, self.0) }
+}
+
+impl PixelFormat for Rgba8 {
+    fn from_rgba(c: Rgba) -> Rgba8 {
+        let (r, g, b) = c.color().to_srgb();
+        Rgba8 { r, g, b, a: alpha_to_u8(c.a) }
+    }
+    fn to_rgba(self) -> Rgba {
+        Rgba::from_pixel((self.r, self.g, self.b), self.a)
+    }
+    fn to_display(self, _: &ToneMapping) -> Pixel { (self.r, self.g, self.b) }
+}
+
+impl PixelFormat for Rgb565 {
+    fn from_rgba(c: Rgba) -> Rgb565 {
+        let (r, g, b) = c.color().to_srgb();
+        Rgb565(to_bits(r, 5) << 11 | to_bits(g, 6) << 5 | to_bits(b, 5))
+    }
+    fn to_rgba(self) -> Rgba { Rgba::from(self.unpack()) }
+    fn to_display(self, _: &ToneMapping) -> Pixel { self.unpack() }
+}
+
+impl Rgb565 {
+    fn unpack(self) -> Pixel {
+        let v = self.0;
+        (from_bits(v >> 11, 5), from_bits(v >> 5 & 0x3f, 6),
+         from_bits(v & 0x1f, 5))
+    }
+}
+
+impl PixelFormat for R32f {
+    fn from_rgba(c: Rgba) -> R32f { R32f(c.color().luminance()) }
+    fn to_rgba(self) -> Rgba { gray(self.0) }
+    fn to_display(self, tone_mapping: &ToneMapping) -> Pixel {
+        tone_mapping.map(Color::new(self.0, self.0, self.0))
+    }
+}
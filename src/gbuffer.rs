@@ -0,0 +1,276 @@
+use std::io;
+use std::io::Write;
+
+use netpbm;
+use pixel::Pixel;
+use types::*;
+use utils::copy_rect;
+
+
+// Marks g-buffer pixels, objects and triangles with no identity.
+pub const NO_ID: u32 = u32::MAX;
+
+// Auxiliary per-pixel buffers filled alongside color during rasterization.
+pub struct GBuffer {
+    pub w: Dimension,
+    pub h: Dimension,
+    pub normals:          Vec<Point>, // View space.
+    pub positions:        Vec<Point>, // World space.
+    pub object_ids:       Vec<u32>,
+    pub triangle_indices: Vec<u32>,
+}
+
+// What the rasterizer is currently drawing, used to fill in the g-buffer.
+#[derive(Clone, Copy)]
+pub struct Surface {
+    pub object_id: u32,
+    pub triangle_index: u32,
+    pub normal: Point,
+
+    // Screen x and y with view depth in z, and the matching world points.
+    screen: [Point; 3],
+    world:  [Point; 3],
+}
+
+// Mutable access to a g-buffer as a rectangle of a larger one, with its
+// top left corner at (x0, y0). Coordinates are those of the larger buffer.
+pub struct GBufferRect<'a> {
+    pub x0: PixCoord,
+    pub y0: PixCoord,
+    pub w: Dimension,
+    normals:          &'a mut [Point],
+    positions:        &'a mut [Point],
+    object_ids:       &'a mut [u32],
+    triangle_indices: &'a mut [u32],
+}
+
+impl<'a> GBufferRect<'a> {
+    pub fn set(
+        &mut self,
+        x: PixCoord,
+        y: PixCoord,
+        surface: &Surface,
+    ) {
+        let index = (y - self.y0) as usize * self.w as usize
+            + (x - self.x0) as usize;
+        self.normals[index]          = surface.normal;
+        self.positions[index]        = surface.position_at(x, y);
+        self.object_ids[index]       = surface.object_id;
+        self.triangle_indices[index] = surface.triangle_index;
+    }
+
+    // Empties a pixel, as when something that isn't a surface, like a line,
+    // is drawn over it.
+    pub fn unset(&mut self, x: PixCoord, y: PixCoord) {
+        let index = (y - self.y0) as usize * self.w as usize
+            + (x - self.x0) as usize;
+        self.normals[index]          = pt![0., 0., 0.];
+        self.positions[index]        = pt![0., 0., 0.];
+        self.object_ids[index]       = NO_ID;
+        self.triangle_indices[index] = NO_ID;
+    }
+}
+
+impl Surface {
+    pub fn new(
+        object_id: u32,
+        triangle_index: u32,
+        normal: Point,
+        screen: Triangle,
+        world: Triangle,
+    ) -> Surface {
+        Surface {
+            object_id,
+            triangle_index,
+            normal,
+            screen: screen.to_arr(),
+            world:  world.to_arr(),
+        }
+    }
+
+    // Returns the world point under the given screen pixel, interpolated
+    // with perspective correction.
+    pub fn position_at(&self, x: PixCoord, y: PixCoord) -> Point {
+        let (x, y) = (x as Coord, y as Coord);
+        let [a, b, c] = self.screen;
+        let area = (b.x - a.x) * (c.y - a.y) - (c.x - a.x) * (b.y - a.y);
+        if area == 0. {
+            return (self.world[0] + self.world[1] + self.world[2])
+                * (1. / 3.);
+        }
+
+        let weights = [
+            ((b.x - x) * (c.y - y) - (c.x - x) * (b.y - y)) / area,
+            ((c.x - x) * (a.y - y) - (a.x - x) * (c.y - y)) / area,
+            ((a.x - x) * (b.y - y) - (b.x - x) * (a.y - y)) / area,
+        ];
+
+        // Weight each vertex by 1 / depth so the result is correct in
+        // world space rather than in screen space.
+        let mut total = 0.;
+        let mut position = pt![0., 0., 0.];
+        for ((weight, screen), world) in
+            weights.iter().zip(self.screen.iter()).zip(self.world.iter())
+        {
+            let w = weight / screen.z;
+            position = position + *world * w;
+            total += w;
+        }
+        position * (1. / total)
+    }
+}
+
+impl GBuffer {
+    pub fn new(w: Dimension, h: Dimension) -> GBuffer {
+        let num_pixels = w as usize * h as usize;
+        GBuffer {
+            w,
+            h,
+            normals:          vec![pt![0., 0., 0.]; num_pixels],
+            positions:        vec![pt![0., 0., 0.]; num_pixels],
+            object_ids:       vec![NO_ID; num_pixels],
+            triangle_indices: vec![NO_ID; num_pixels],
+        }
+    }
+
+    pub fn set(
+        &mut self,
+        x: PixCoord,
+        y: PixCoord,
+        surface: &Surface,
+    ) {
+        self.rect_mut().set(x, y, surface);
+    }
+
+    pub fn unset(&mut self, x: PixCoord, y: PixCoord) {
+        self.rect_mut().unset(x, y);
+    }
+
+    pub fn rect_mut(&mut self) -> GBufferRect<'_> { self.rect_at(0, 0) }
+
+    // The buffer as the rectangle of a larger one at (x0, y0).
+    pub fn rect_at(&mut self, x0: PixCoord, y0: PixCoord) -> GBufferRect<'_> {
+        GBufferRect {
+            x0,
+            y0,
+            w: self.w,
+            normals:          &mut self.normals,
+            positions:        &mut self.positions,
+            object_ids:       &mut self.object_ids,
+            triangle_indices: &mut self.triangle_indices,
+        }
+    }
+
+    // A copy of the w by h rectangle with its top left corner at (x, y).
+    pub fn copy_rect(
+        &self,
+        x: Dimension,
+        y: Dimension,
+        w: Dimension,
+        h: Dimension,
+    ) -> GBuffer {
+        let mut rect = GBuffer::new(w, h);
+        let (from, size) = ((x as usize, y as usize), (w as usize, h as usize));
+        let (self_w, w) = (self.w as usize, w as usize);
+        copy_rect(&self.normals, self_w, from,
+                  &mut rect.normals, w, (0, 0), size);
+        copy_rect(&self.positions, self_w, from,
+                  &mut rect.positions, w, (0, 0), size);
+        copy_rect(&self.object_ids, self_w, from,
+                  &mut rect.object_ids, w, (0, 0), size);
+        copy_rect(&self.triangle_indices, self_w, from,
+                  &mut rect.triangle_indices, w, (0, 0), size);
+        rect
+    }
+
+    // Writes back a rectangle from copy_rect, to (x, y).
+    pub fn paste_rect(
+        &mut self,
+        x: Dimension,
+        y: Dimension,
+        rect: &GBuffer,
+    ) {
+        let (to, size) = ((x as usize, y as usize),
+                          (rect.w as usize, rect.h as usize));
+        let (self_w, w) = (self.w as usize, rect.w as usize);
+        copy_rect(&rect.normals, w, (0, 0),
+                  &mut self.normals, self_w, to, size);
+        copy_rect(&rect.positions, w, (0, 0),
+                  &mut self.positions, self_w, to, size);
+        copy_rect(&rect.object_ids, w, (0, 0),
+                  &mut self.object_ids, self_w, to, size);
+        copy_rect(&rect.triangle_indices, w, (0, 0),
+                  &mut self.triangle_indices, self_w, to, size);
+    }
+
+    pub fn clear(&mut self) {
+        for i in 0 .. self.object_ids.len() {
+            self.normals[i]          = pt![0., 0., 0.];
+            self.positions[i]        = pt![0., 0., 0.];
+            self.object_ids[i]       = NO_ID;
+            self.triangle_indices[i] = NO_ID;
+        }
+    }
+
+    // Writes normals as a PPM, mapping each component from [-1, 1] to
+    // [0, 255]. Empty pixels come out gray.
+    pub fn write_normals<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let to_byte = |c: Coord| ((c * 0.5 + 0.5) * 255.).round() as u8;
+        let pixels: Vec<Pixel> = self.normals.iter()
+            .map(|n| (to_byte(n.x), to_byte(n.y), to_byte(n.z)))
+            .collect();
+        netpbm::write_ppm(out, self.w, self.h, &pixels)
+    }
+
+    // Writes world positions as a three-channel PFM.
+    pub fn write_positions<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let values: Vec<f32> = self.positions.iter()
+            .flat_map(|p| vec![p.x as f32, p.y as f32, p.z as f32])
+            .collect();
+        netpbm::write_pfm(out, self.w, self.h, 3, &values)
+    }
+
+    // Writes object IDs as a 16-bit PGM holding ID + 1, so that empty
+    // pixels are 0. IDs past the 16-bit range saturate.
+    pub fn write_object_ids<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write_ids(out, self.w, self.h, &self.object_ids)
+    }
+
+    // Writes triangle indices the same way as write_object_ids.
+    pub fn write_triangle_indices<W: Write>(&self, out: &mut W)
+        -> io::Result<()>
+    {
+        write_ids(out, self.w, self.h, &self.triangle_indices)
+    }
+
+    pub fn save_normals(&self, filename: &str) -> io::Result<()> {
+        self.write_normals(&mut netpbm::create(filename)?)
+    }
+
+    pub fn save_positions(&self, filename: &str) -> io::Result<()> {
+        self.write_positions(&mut netpbm::create(filename)?)
+    }
+
+    pub fn save_object_ids(&self, filename: &str) -> io::Result<()> {
+        self.write_object_ids(&mut netpbm::create(filename)?)
+    }
+
+    pub fn save_triangle_indices(&self, filename: &str) -> io::Result<()> {
+        self.write_triangle_indices(&mut netpbm::create(filename)?)
+    }
+}
+
+fn write_ids<W: Write>(
+    out: &mut W,
+    w: Dimension,
+    h: Dimension,
+    ids: &[u32],
+) -> io::Result<()> {
+    let values: Vec<u16> = ids.iter()
+        .map(|&id| {
+            if id == NO_ID { 0 }
+            else { id.saturating_add(1).min(0xffff) as u16 }
+        })
+        .collect();
+    netpbm::write_pgm16(out, w, h, &values)
+}
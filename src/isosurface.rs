@@ -0,0 +1,338 @@
+// Isosurface extraction from scalar fields by marching cubes. Values below
+// the iso level are inside the surface, so signed distance functions work
+// as they are, and normals point along the field's gradient.
+
+use std::error;
+use std::fs;
+use std::io::Read;
+
+use object::Object;
+use types::*;
+
+
+// A grid of samples of a scalar field.
+pub struct Volume {
+    pub nx: usize,
+    pub ny: usize,
+    pub nz: usize,
+    // World position of the first sample, and the distance between samples
+    // along each axis.
+    pub origin:  Point,
+    pub spacing: Point,
+    values: Vec<Coord>,
+}
+
+// Sample formats for raw voxel files. Multi-byte samples are little-endian.
+#[derive(Clone, Copy, Debug)]
+pub enum RawFormat {
+    U8,
+    U16,
+    F32,
+}
+
+impl RawFormat {
+    fn size(self) -> usize {
+        match self {
+            RawFormat::U8  => 1,
+            RawFormat::U16 => 2,
+            RawFormat::F32 => 4,
+        }
+    }
+}
+
+impl Volume {
+    pub fn new(nx: usize, ny: usize, nz: usize, values: Vec<Coord>) -> Volume {
+        assert!(values.len() == nx * ny * nz);
+        Volume {
+            nx,
+            ny,
+            nz,
+            origin:  pt![0., 0., 0.],
+            spacing: pt![1., 1., 1.],
+            values,
+        }
+    }
+
+    // Samples f at each grid point, with x varying fastest.
+    pub fn from_fn<F>(
+        nx: usize,
+        ny: usize,
+        nz: usize,
+        origin: Point,
+        spacing: Point,
+        f: F,
+    ) -> Volume
+        where F: Fn(Point) -> Coord
+    {
+        let mut values = Vec::with_capacity(nx * ny * nz);
+        for k in 0 .. nz {
+            for j in 0 .. ny {
+                for i in 0 .. nx {
+                    values.push(f(grid_point(origin, spacing, i, j, k)));
+                }
+            }
+        }
+        Volume { nx, ny, nz, origin, spacing, values }
+    }
+
+    // Loads a headerless file of nx * ny * nz samples, with x varying
+    // fastest. Integer samples are scaled to [0, 1].
+    pub fn from_raw_file(
+        filename: &str,
+        nx: usize,
+        ny: usize,
+        nz: usize,
+        format: RawFormat,
+    ) -> Result<Volume, Box<error::Error>> {
+        let mut data = Vec::new();
+        fs::File::open(filename)?.read_to_end(&mut data)?;
+
+        let expected = nx * ny * nz * format.size();
+        if data.len() != expected {
+            return Err(From::from(format!(
+                "expected {} bytes of voxels, found {}",
+                expected,
+                data.len()
+            )));
+        }
+
+        let values = data.chunks(format.size())
+            .map(|b| match format {
+                RawFormat::U8  => b[0] as Coord / 255.,
+                RawFormat::U16 =>
+                    (b[0] as u16 | (b[1] as u16) << 8) as Coord / 65535.,
+                RawFormat::F32 => f32::from_bits(
+                    b[0] as u32
+                        | (b[1] as u32) << 8
+                        | (b[2] as u32) << 16
+                        | (b[3] as u32) << 24
+                ) as Coord,
+            })
+            .collect();
+        Ok(Volume::new(nx, ny, nz, values))
+    }
+
+    pub fn with_placement(mut self, origin: Point, spacing: Point) -> Volume {
+        self.origin = origin;
+        self.spacing = spacing;
+        self
+    }
+
+    pub fn get(&self, i: usize, j: usize, k: usize) -> Coord {
+        self.values[(k * self.ny + j) * self.nx + i]
+    }
+
+    pub fn position(&self, i: usize, j: usize, k: usize) -> Point {
+        grid_point(self.origin, self.spacing, i, j, k)
+    }
+
+    // Central differences, one-sided at the edges of the grid.
+    pub fn gradient(&self, i: usize, j: usize, k: usize) -> Point {
+        let (i0, i1) = (i.saturating_sub(1), (i + 1).min(self.nx - 1));
+        let (j0, j1) = (j.saturating_sub(1), (j + 1).min(self.ny - 1));
+        let (k0, k1) = (k.saturating_sub(1), (k + 1).min(self.nz - 1));
+        let slope = |lo: Coord, hi: Coord, steps: usize, spacing: Coord| {
+            if steps == 0 { 0. } else { (hi - lo) / (steps as Coord * spacing) }
+        };
+        let (dx, dy, dz) = (i1 - i0, j1 - j0, k1 - k0);
+        pt![
+            slope(self.get(i0, j, k), self.get(i1, j, k), dx, self.spacing.x),
+            slope(self.get(i, j0, k), self.get(i, j1, k), dy, self.spacing.y),
+            slope(self.get(i, j, k0), self.get(i, j, k1), dz, self.spacing.z)
+        ]
+    }
+}
+
+fn grid_point(origin: Point, spacing: Point, i: usize, j: usize, k: usize)
+    -> Point
+{
+    pt![
+        origin.x + spacing.x * i as Coord,
+        origin.y + spacing.y * j as Coord,
+        origin.z + spacing.z * k as Coord
+    ]
+}
+
+
+// Cube corners are numbered by their offsets, with x in bit 0, y in bit 1
+// and z in bit 2. Edges join corners that differ in one bit.
+const EDGES: [(usize, usize); 12] = [
+    (0, 1), (2, 3), (4, 5), (6, 7), // Along x.
+    (0, 2), (1, 3), (4, 6), (5, 7), // Along y.
+    (0, 4), (1, 5), (2, 6), (3, 7), // Along z.
+];
+
+fn edge_index(a: usize, b: usize) -> usize {
+    let key = (a.min(b), a.max(b));
+    EDGES.iter().position(|&e| e == key).unwrap()
+}
+
+// Builds, for each of the 256 ways corners can be inside, the triangles to
+// emit as triples of edge indices. Contours are traced over each face of the
+// cube and joined into loops. Faces with diagonally opposite inside corners
+// cut off each inside corner, which neighboring cubes agree on, so the
+// surface has no holes.
+fn triangle_table() -> Vec<Vec<[usize; 3]>> {
+    // Each face's corners in order around it.
+    let mut faces = Vec::new();
+    for axis in 0 .. 3 {
+        let (b, c) = ((axis + 1) % 3, (axis + 2) % 3);
+        for side in 0 .. 2 {
+            let corner = |u: usize, v: usize| side << axis | u << b | v << c;
+            faces.push(
+                [corner(0, 0), corner(1, 0), corner(1, 1), corner(0, 1)]
+            );
+        }
+    }
+
+    (0 .. 256usize).map(|case| {
+        let inside = |corner: usize| case & (1 << corner) != 0;
+
+        // Segments between crossed edges on each face.
+        let mut segments: Vec<(usize, usize)> = Vec::new();
+        for face in &faces {
+            let crossed: Vec<usize> = (0 .. 4)
+                .filter(|&n| inside(face[n]) != inside(face[(n + 1) % 4]))
+                .collect();
+            match crossed.len() {
+                2 => segments.push((
+                    edge_index(face[crossed[0]], face[(crossed[0] + 1) % 4]),
+                    edge_index(face[crossed[1]], face[(crossed[1] + 1) % 4]),
+                )),
+                4 => {
+                    // Pair the edges either side of each inside corner.
+                    for n in 0 .. 4 {
+                        if !inside(face[n]) { continue }
+                        segments.push((
+                            edge_index(face[(n + 3) % 4], face[n]),
+                            edge_index(face[n], face[(n + 1) % 4]),
+                        ));
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        // Every crossed edge is on two faces, so the segments form loops.
+        let mut triangles = Vec::new();
+        while let Some((first, mut current)) = segments.pop() {
+            let mut polygon = vec![first];
+            while current != first {
+                polygon.push(current);
+                let next = segments.iter()
+                    .position(|&(a, b)| a == current || b == current)
+                    .unwrap();
+                let (a, b) = segments.swap_remove(next);
+                current = if a == current { b } else { a };
+            }
+            for n in 1 .. polygon.len() - 1 {
+                triangles.push([polygon[0], polygon[n], polygon[n + 1]]);
+            }
+        }
+        triangles
+    }).collect()
+}
+
+// Extracts the surface where the volume crosses `iso`.
+pub fn marching_cubes(volume: &Volume, iso: Coord) -> Object {
+    march(
+        volume,
+        iso,
+        |i, j, k| volume.gradient(i, j, k),
+        |_| None,
+    )
+}
+
+// Extracts the surface where f crosses zero, sampling it on a grid of
+// resolution points per axis over the box from min to max. Normals come
+// from f's gradient at each vertex.
+pub fn marching_cubes_fn<F>(f: F, min: Point, max: Point, resolution: usize)
+    -> Object
+    where F: Fn(Point) -> Coord
+{
+    let n = resolution.max(2);
+    let extent = max - min;
+    let steps = (n - 1) as Coord;
+    let spacing = pt![extent.x / steps, extent.y / steps, extent.z / steps];
+    let volume = Volume::from_fn(n, n, n, min, spacing, &f);
+
+    let h = spacing.magnitude() * 1e-3;
+    march(
+        &volume,
+        0.,
+        |i, j, k| volume.gradient(i, j, k),
+        |p| Some(pt![
+            f(p + pt![h, 0., 0.]) - f(p - pt![h, 0., 0.]),
+            f(p + pt![0., h, 0.]) - f(p - pt![0., h, 0.]),
+            f(p + pt![0., 0., h]) - f(p - pt![0., 0., h])
+        ]),
+    )
+}
+
+fn march<G, E>(
+    volume: &Volume,
+    iso: Coord,
+    grid_gradient: G,
+    exact_gradient: E,
+) -> Object
+    where G: Fn(usize, usize, usize) -> Point,
+          E: Fn(Point) -> Option<Point>
+{
+    let table = triangle_table();
+    let mut tris = Vec::new();
+    let mut normals = Vec::new();
+
+    for k in 0 .. volume.nz.saturating_sub(1) {
+        for j in 0 .. volume.ny.saturating_sub(1) {
+            for i in 0 .. volume.nx.saturating_sub(1) {
+                let corner = |c: usize| {
+                    (i + (c & 1), j + (c >> 1 & 1), k + (c >> 2))
+                };
+                let value = |c: usize| {
+                    let (x, y, z) = corner(c);
+                    volume.get(x, y, z)
+                };
+                let case = (0 .. 8)
+                    .filter(|&c| value(c) < iso)
+                    .fold(0, |case, c| case | 1 << c);
+
+                // Vertices on edges run from the lower corner to the upper,
+                // so that neighboring cubes compute identical positions.
+                let vertex = |e: usize| {
+                    let (a, b) = EDGES[e];
+                    let (va, vb) = (value(a), value(b));
+                    let t = if va == vb { 0.5 } else { (iso - va) / (vb - va) };
+                    let ((ax, ay, az), (bx, by, bz)) = (corner(a), corner(b));
+                    let pa = volume.position(ax, ay, az);
+                    let pb = volume.position(bx, by, bz);
+                    let p = pa + (pb - pa) * t;
+                    let n = exact_gradient(p).unwrap_or_else(|| {
+                        let ga = grid_gradient(ax, ay, az);
+                        let gb = grid_gradient(bx, by, bz);
+                        ga + (gb - ga) * t
+                    });
+                    (p, n.normalized())
+                };
+
+                for tri in &table[case] {
+                    let (a, b, c) =
+                        (vertex(tri[0]), vertex(tri[1]), vertex(tri[2]));
+                    let t = trigon![a.0, b.0, c.0];
+                    let face = (t.p2 - t.p1).cross(t.p3 - t.p1);
+                    if face.magnitude() == 0. { continue }
+
+                    // Face along the gradient, out of the surface.
+                    if face.dot(a.1 + b.1 + c.1) >= 0. {
+                        tris.push(t);
+                        normals.push([a.1, b.1, c.1]);
+                    } else {
+                        tris.push(trigon![a.0, c.0, b.0]);
+                        normals.push([a.1, c.1, b.1]);
+                    }
+                }
+            }
+        }
+    }
+
+    Object::with_normals(tris, normals)
+}
@@ -1,10 +1,31 @@
 extern crate sdl2;
 
 #[macro_use] pub mod types;
+pub mod abuffer;
+pub mod animation;
+pub mod antialias;
+pub mod bounds;
+pub mod bvh;
+pub mod depth;
+pub mod format;
+pub mod gbuffer;
+pub mod isosurface;
+pub mod lod;
+pub mod mesh;
 pub mod object;
 pub mod pixel;
+pub mod primitives;
 pub mod renderer;
+pub mod scene;
+pub mod scenefile;
 pub mod screen;
+pub mod simplify;
+pub mod skinning;
+pub mod subdivide;
+pub mod terrain;
+pub mod texture;
+pub mod tiles;
+pub mod tonemap;
 
 use sdl2::event::Event as SdlEvent;
 
@@ -13,9 +34,10 @@ use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 
-mod texture;
+mod netpbm;
 mod utils;
 
+use antialias::Antialiasing;
 use renderer::Renderer;
 use screen::GraphicalScreen;
 
@@ -51,6 +73,7 @@ pub fn main_loop<'a, WorldState>
         &sdl_context,
     ));
     let mut renderer = Renderer::new(screen);
+    renderer.set_antialiasing(screen_config.antialiasing);
     let mut event_pump = sdl_context.event_pump().unwrap();
 
     let mut world_state = try!(init(&mut renderer));
@@ -89,7 +112,8 @@ pub struct ScreenConfig {
     pub title:  &'static str,
     pub width:  u32,
     pub height: u32,
-    pub target_fps: u32
+    pub target_fps: u32,
+    pub antialiasing: Antialiasing,
 }
 
 pub struct LoopState {
@@ -0,0 +1,93 @@
+// Levels of detail: a set of versions of one object, from finest to
+// coarsest, picked between by how large the object appears on screen.
+
+
+use format::PixelFormat;
+use object::Object;
+use renderer::Renderer;
+use screen::Screen;
+use simplify::Target;
+use types::*;
+
+
+pub struct LodSet {
+    levels: Vec<LodLevel>,
+}
+
+pub struct LodLevel {
+    pub object: Object,
+    // This level is used when the object spans fewer pixels than this.
+    pub max_size: Coord,
+}
+
+impl LodSet {
+    pub fn new(base: Object) -> LodSet {
+        LodSet {
+            levels: vec![LodLevel { object: base, max_size: Coord::INFINITY }],
+        }
+    }
+
+    // Builds coarser levels by simplifying the base object. Each level is
+    // given as a target triangle count and the screen size in pixels below
+    // which it is used.
+    pub fn from_object(base: Object, levels: &[(usize, Coord)]) -> LodSet {
+        let mut set = LodSet::new(base);
+        for &(triangles, max_size) in levels {
+            let level = set.levels[0].object
+                .simplified(Target::triangles(triangles));
+            set.add_level(level, max_size);
+        }
+        set
+    }
+
+    // Adds a level, keeping them sorted from finest to coarsest. It takes
+    // the base object's ID, so that every level picks as the same object.
+    pub fn add_level(&mut self, object: Object, max_size: Coord) {
+        let object = object.with_id(self.levels[0].object.id());
+        let i = self.levels.iter()
+            .position(|l| l.max_size < max_size)
+            .unwrap_or(self.levels.len());
+        self.levels.insert(i, LodLevel { object, max_size });
+    }
+
+    pub fn levels(&self) -> &[LodLevel] { &self.levels }
+
+    // Picks the coarsest level allowed at the given screen size.
+    pub fn select(&self, size: Coord) -> &Object {
+        &self.levels.iter()
+            .rev()
+            .find(|l| size < l.max_size)
+            .unwrap_or(&self.levels[0])
+            .object
+    }
+
+    pub fn render<S: Screen, P: PixelFormat>(
+        &self,
+        renderer: &mut Renderer<S, P>,
+    ) {
+        let sphere = self.levels[0].object.bounding_sphere();
+        let size = renderer.projected_size(&sphere);
+        self.select(size).render(renderer);
+    }
+
+
+    pub fn translate(&mut self, off: Point) {
+        for l in &mut self.levels { l.object.translate(off) }
+    }
+
+    pub fn scale(&mut self, x: Coord, y: Coord, z: Coord) {
+        for l in &mut self.levels { l.object.scale(x, y, z) }
+    }
+
+    pub fn rotate_x(&mut self, theta: Coord) {
+        for l in &mut self.levels { l.object.rotate_x(theta) }
+    }
+
+    pub fn rotate_y(&mut self, theta: Coord) {
+        for l in &mut self.levels { l.object.rotate_y(theta) }
+    }
+
+    pub fn rotate_z(&mut self, theta: Coord) {
+        for l in &mut self.levels { l.object.rotate_z(theta) }
+    }
+}
@@ -4,26 +4,29 @@ extern crate sdl2;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 
-use rusterize::object::Object;
+use rusterize::antialias::Antialiasing;
 use rusterize::renderer::LightingMode;
 use rusterize::renderer::Renderer;
+use rusterize::scene::NodeId;
+use rusterize::scene::Scene;
+use rusterize::scenefile;
 use rusterize::screen::Screen;
 use rusterize::types::*;
 
 use std::cmp::min;
 use std::error;
-use std::f64;
 use std::process;
 
 
 pub const SCREEN_WIDTH:  u32 = 800;
 pub const SCREEN_HEIGHT: u32 = 600;
 pub const TARGET_FPS:    u32 = 60;
-pub const TIME_PER_TICK: f64 = 1. / (TARGET_FPS as f64);
+pub const TIME_PER_TICK: Coord = 1. / (TARGET_FPS as Coord);
 
 struct WorldState {
-    time: f64,
-    objects: Vec<Object>
+    time: Coord,
+    scene: Scene,
+    cube: NodeId,
 }
 
 
@@ -34,6 +37,7 @@ fn main() {
             width:      SCREEN_WIDTH,
             height:     SCREEN_HEIGHT,
             target_fps: TARGET_FPS,
+            antialiasing: Antialiasing::Multisample(4),
         },
         init,
         parse_event,
@@ -52,7 +56,7 @@ fn init<S: Screen>(renderer: &mut Renderer<S>)
 {
     // Set perspective transform.
     renderer.set_transform({
-        let screen_scale = (min(SCREEN_WIDTH, SCREEN_HEIGHT) / 2) as f64;
+        let screen_scale = (min(SCREEN_WIDTH, SCREEN_HEIGHT) / 2) as Coord;
         Transform::translate(pt_2d![
             (SCREEN_WIDTH  / 2) as Coord,
             (SCREEN_HEIGHT / 2) as Coord
@@ -61,29 +65,17 @@ fn init<S: Screen>(renderer: &mut Renderer<S>)
         * Transform::perspective()
     });
 
-    // Set up lighting.
-    renderer.set_light_pos(pt![10., 0., 10.]);
     renderer.set_lighting_mode(LightingMode::FlatShading);
 
-    // State variables.
-    let objects: Vec<Object> = {
-        let mut objects = Vec::new();
-
-        objects.push({
-            let size = 3.;
-            Object::from_file("res/cube.obj")?
-                .scaled(size, size, size)
-                .translated(pt![0., 0., -20.])
-                .rotated_x(f64::consts::PI / 4.)
-        });
-
-        objects
-    };
+    // Load the scene.
+    let scene = scenefile::load_scene("res/demo.scene")?;
+    let cube = scene.find("cube").ok_or("the demo scene has no cube")?;
 
     Ok(
         WorldState {
             time: 0.,
-            objects: objects,
+            scene,
+            cube,
         }
     )
 }
@@ -114,8 +106,9 @@ fn parse_event(
 // Returns true if the frame is made dirty, else false.
 fn update(world_state: &mut WorldState) -> bool {
     world_state.time += TIME_PER_TICK;
-    world_state.objects[0].rotate_y(TIME_PER_TICK);
-    world_state.objects[0].rotate_x(TIME_PER_TICK);
+    let cube = world_state.cube;
+    world_state.scene.rotate(cube, Quaternion::rotate_y(TIME_PER_TICK));
+    world_state.scene.rotate(cube, Quaternion::rotate_x(TIME_PER_TICK));
 
     true // frame dirty
 }
@@ -126,10 +119,8 @@ fn render<T: Screen>(
 )
     -> Result<(), Box<error::Error>>
 {
-    renderer.clear();
-    for object in &world_state.objects {
-        object.render(renderer);
-    }
+    renderer.clear_to(world_state.scene.background);
+    renderer.render_scene(&world_state.scene);
     try!(renderer.display());
     Ok(())
 }
@@ -0,0 +1,274 @@
+// Meshes with shared vertices, for algorithms that need to know which faces
+// touch. Object stores unshared triangles, so vertices are welded by exact
+// position on the way in.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use types::*;
+
+
+// A triangle mesh.
+#[derive(Clone, Debug)]
+pub struct IndexedMesh {
+    pub positions: Vec<Point>,
+    // Vertex indices in counter-clockwise order, as in Triangle.
+    pub faces: Vec<[usize; 3]>,
+}
+
+impl IndexedMesh {
+    pub fn from_triangles(tris: &[Triangle]) -> IndexedMesh {
+        let mut positions = Vec::new();
+        let mut indices = HashMap::new();
+        let mut index_of = |p: Point| {
+            // Fold -0 into 0 so that they weld.
+            let key = [
+                (p.x + 0.).to_bits(),
+                (p.y + 0.).to_bits(),
+                (p.z + 0.).to_bits(),
+            ];
+            *indices.entry(key).or_insert_with(|| {
+                positions.push(p);
+                positions.len() - 1
+            })
+        };
+
+        let faces = tris.iter()
+            .map(|t| [index_of(t.p1), index_of(t.p2), index_of(t.p3)])
+            .collect();
+        IndexedMesh { positions, faces }
+    }
+
+    pub fn to_triangles(&self) -> Vec<Triangle> {
+        self.faces.iter()
+            .map(|f| trigon![
+                self.positions[f[0]],
+                self.positions[f[1]],
+                self.positions[f[2]]
+            ])
+            .collect()
+    }
+
+    // Counts how many faces use each undirected edge, keyed with the lower
+    // vertex index first.
+    pub fn edge_face_counts(&self) -> HashMap<(usize, usize), usize> {
+        let mut counts = HashMap::new();
+        for f in &self.faces {
+            for k in 0 .. 3 {
+                *counts.entry(edge_key(f[k], f[(k + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+}
+
+pub fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+
+// A mesh of polygons with shared vertices, such as quads from a cage mesh.
+#[derive(Clone, Debug)]
+pub struct PolyMesh {
+    pub positions: Vec<Point>,
+    // Vertex indices in the same winding as Triangle.
+    pub faces: Vec<Vec<usize>>,
+}
+
+impl PolyMesh {
+    pub fn from_triangles(tris: &[Triangle]) -> PolyMesh {
+        PolyMesh::from(IndexedMesh::from_triangles(tris))
+    }
+
+    // Like from_triangles, but joins pairs of coplanar triangles that share
+    // an edge back into the quads they were likely split from.
+    pub fn from_triangles_merging_quads(tris: &[Triangle]) -> PolyMesh {
+        const COPLANAR: Coord = 1. - 1e-9;
+
+        let mesh = IndexedMesh::from_triangles(tris);
+        let normal = |f: &[usize; 3]| {
+            let p = |i: usize| mesh.positions[f[i]];
+            (p(1) - p(0)).cross(p(2) - p(0)).normalized()
+        };
+
+        // Find, for each directed edge, the face it belongs to.
+        let mut edge_faces = HashMap::new();
+        for (i, f) in mesh.faces.iter().enumerate() {
+            for k in 0 .. 3 { edge_faces.insert((f[k], f[(k + 1) % 3]), i); }
+        }
+
+        let mut merged = vec![false; mesh.faces.len()];
+        let mut faces = Vec::with_capacity(mesh.faces.len());
+        for (i, f) in mesh.faces.iter().enumerate() {
+            if merged[i] { continue }
+            merged[i] = true;
+
+            // Pick the neighbor across this triangle's longest edge, which
+            // is the diagonal of the quad if there is one.
+            let k = (0 .. 3).max_by(|&a, &b| {
+                let len = |k: usize| (mesh.positions[f[(k + 1) % 3]]
+                                      - mesh.positions[f[k]]).magnitude();
+                len(a).partial_cmp(&len(b)).unwrap_or(Ordering::Equal)
+            }).unwrap();
+            let (a, b, c) = (f[k], f[(k + 1) % 3], f[(k + 2) % 3]);
+            let quad = edge_faces.get(&(b, a)).cloned()
+                .filter(|&j| !merged[j])
+                .filter(|&j| normal(f).dot(normal(&mesh.faces[j])) >= COPLANAR)
+                .map(|j| {
+                    let g = mesh.faces[j];
+                    let d = g.iter().cloned()
+                        .find(|&v| v != a && v != b)
+                        .unwrap();
+                    (j, d)
+                });
+            match quad {
+                Some((j, d)) => {
+                    merged[j] = true;
+                    faces.push(vec![a, d, b, c]);
+                },
+                None => faces.push(f.to_vec()),
+            }
+        }
+
+        PolyMesh { positions: mesh.positions, faces }
+    }
+
+    // Splits each polygon into a fan of triangles.
+    pub fn triangulate(&self) -> IndexedMesh {
+        let faces = self.faces.iter()
+            .flat_map(|f| {
+                (1 .. f.len() - 1).map(move |i| [f[0], f[i], f[i + 1]])
+            })
+            .collect();
+        IndexedMesh { positions: self.positions.clone(), faces }
+    }
+
+    pub fn to_triangles(&self) -> Vec<Triangle> {
+        self.triangulate().to_triangles()
+    }
+}
+
+impl From<IndexedMesh> for PolyMesh {
+    fn from(mesh: IndexedMesh) -> PolyMesh {
+        PolyMesh {
+            positions: mesh.positions,
+            faces: mesh.faces.iter().map(|f| f.to_vec()).collect(),
+        }
+    }
+}
+
+
+// Half-edge connectivity for a polygon mesh. Each face is a loop of
+// half-edges; half-edges on a boundary, or on an edge shared by more than
+// two faces, have no twin.
+#[derive(Clone, Debug)]
+pub struct HalfEdgeMesh {
+    pub half_edges: Vec<HalfEdge>,
+    // One half-edge on each face.
+    pub face_edges: Vec<usize>,
+    // Half-edges leaving each vertex.
+    vertex_edges: Vec<Vec<usize>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct HalfEdge {
+    pub origin: usize,
+    pub next:   usize,
+    pub twin:   Option<usize>,
+    pub face:   usize,
+}
+
+impl HalfEdgeMesh {
+    pub fn new(num_vertices: usize, faces: &[Vec<usize>]) -> HalfEdgeMesh {
+        let mut half_edges = Vec::new();
+        let mut face_edges = Vec::with_capacity(faces.len());
+        let mut vertex_edges = vec![Vec::new(); num_vertices];
+        let mut directed: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+
+        for (f, face) in faces.iter().enumerate() {
+            let first = half_edges.len();
+            face_edges.push(first);
+            for (k, &v) in face.iter().enumerate() {
+                let h = first + k;
+                half_edges.push(HalfEdge {
+                    origin: v,
+                    next: first + (k + 1) % face.len(),
+                    twin: None,
+                    face: f,
+                });
+                vertex_edges[v].push(h);
+                directed.entry((v, face[(k + 1) % face.len()]))
+                    .or_default()
+                    .push(h);
+            }
+        }
+
+        // Pair up half-edges on manifold edges.
+        for (&(a, b), hs) in &directed {
+            if hs.len() != 1 { continue }
+            if let Some(twins) = directed.get(&(b, a)) {
+                if twins.len() == 1 { half_edges[hs[0]].twin = Some(twins[0]) }
+            }
+        }
+
+        HalfEdgeMesh { half_edges, face_edges, vertex_edges }
+    }
+
+    pub fn origin(&self, h: usize) -> usize { self.half_edges[h].origin }
+
+    pub fn dest(&self, h: usize) -> usize {
+        self.half_edges[self.half_edges[h].next].origin
+    }
+
+    pub fn prev(&self, h: usize) -> usize {
+        let mut p = h;
+        while self.half_edges[p].next != h { p = self.half_edges[p].next }
+        p
+    }
+
+    pub fn is_boundary(&self, h: usize) -> bool {
+        self.half_edges[h].twin.is_none()
+    }
+
+    // Picks one half-edge to stand for each undirected edge.
+    pub fn canonical(&self, h: usize) -> usize {
+        match self.half_edges[h].twin {
+            Some(t) if t < h => t,
+            _ => h,
+        }
+    }
+
+    pub fn edges<'a>(&'a self) -> impl Iterator<Item = usize> + 'a {
+        (0 .. self.half_edges.len()).filter(move |&h| self.canonical(h) == h)
+    }
+
+    pub fn outgoing(&self, v: usize) -> &[usize] { &self.vertex_edges[v] }
+
+    // Returns one half-edge for each edge touching a vertex.
+    pub fn vertex_edges(&self, v: usize) -> Vec<usize> {
+        let mut edges: Vec<usize> = self.vertex_edges[v].iter()
+            .flat_map(|&h| {
+                vec![self.canonical(h), self.canonical(self.prev(h))]
+            })
+            .collect();
+        edges.sort();
+        edges.dedup();
+        edges
+    }
+
+    pub fn face_vertices(&self, f: usize) -> Vec<usize> {
+        let first = self.face_edges[f];
+        let mut vertices = vec![self.origin(first)];
+        let mut h = self.half_edges[first].next;
+        while h != first {
+            vertices.push(self.origin(h));
+            h = self.half_edges[h].next;
+        }
+        vertices
+    }
+
+    // Returns the vertex across an edge from `v`.
+    pub fn other(&self, h: usize, v: usize) -> usize {
+        if self.origin(h) == v { self.dest(h) } else { self.origin(h) }
+    }
+}
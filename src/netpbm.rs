@@ -0,0 +1,158 @@
+// Readers and writers for the Netpbm family and PFM, used for loading
+// images and exporting buffers.
+
+use std::error;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+
+use pixel::Pixel;
+use types::*;
+
+
+pub fn create(filename: &str) -> io::Result<io::BufWriter<fs::File>> {
+    Ok(io::BufWriter::new(fs::File::create(filename)?))
+}
+
+// Writes a little-endian PFM with one or three channels per pixel, given as
+// rows from top to bottom.
+pub fn write_pfm<W: Write>(
+    out: &mut W,
+    w: Dimension,
+    h: Dimension,
+    channels: usize,
+    values: &[f32],
+) -> io::Result<()> {
+    assert!(channels == 1 || channels == 3);
+    assert!(values.len() == w as usize * h as usize * channels);
+
+    let magic = if channels == 1 { "Pf" } else { "PF" };
+    write!(out, "{}\n{} {}\n-1.0\n", magic, w, h)?;
+    // PFM rows run from bottom to top.
+    for row in values.chunks(w as usize * channels).rev() {
+        for v in row {
+            let bits = v.to_bits();
+            out.write_all(&[
+                bits as u8,
+                (bits >>  8) as u8,
+                (bits >> 16) as u8,
+                (bits >> 24) as u8,
+            ])?;
+        }
+    }
+    Ok(())
+}
+
+// Writes a binary 16-bit PGM.
+pub fn write_pgm16<W: Write>(
+    out: &mut W,
+    w: Dimension,
+    h: Dimension,
+    values: &[u16],
+) -> io::Result<()> {
+    assert!(values.len() == w as usize * h as usize);
+
+    write!(out, "P5\n{} {}\n65535\n", w, h)?;
+    for &v in values {
+        out.write_all(&[(v >> 8) as u8, v as u8])?;
+    }
+    Ok(())
+}
+
+// Writes a binary 8-bit PPM.
+pub fn write_ppm<W: Write>(
+    out: &mut W,
+    w: Dimension,
+    h: Dimension,
+    pixels: &[Pixel],
+) -> io::Result<()> {
+    assert!(pixels.len() == w as usize * h as usize);
+
+    write!(out, "P6\n{} {}\n255\n", w, h)?;
+    for &(r, g, b) in pixels {
+        out.write_all(&[r, g, b])?;
+    }
+    Ok(())
+}
+
+// A decoded PGM or PPM image, with samples scaled to [0, 1].
+pub struct Image {
+    pub w: Dimension,
+    pub h: Dimension,
+    pub channels: usize,
+    pub samples: Vec<f64>,
+}
+
+// Reads a PGM or PPM image, in plain or binary form.
+pub fn read_pnm<R: Read>(input: &mut R) -> Result<Image, Box<error::Error>> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    let mut pos = 0;
+
+    // Reads the next whitespace-separated header token, skipping comments.
+    let token = |pos: &mut usize| -> Result<String, Box<error::Error>> {
+        loop {
+            while *pos < data.len() && (data[*pos] as char).is_whitespace() {
+                *pos += 1;
+            }
+            if *pos < data.len() && data[*pos] == b'#' {
+                while *pos < data.len() && data[*pos] != b'\n' { *pos += 1 }
+            } else {
+                break;
+            }
+        }
+        let start = *pos;
+        while *pos < data.len() && !(data[*pos] as char).is_whitespace() {
+            *pos += 1;
+        }
+        if start == *pos { return Err(From::from("unexpected end of image")) }
+        Ok(String::from_utf8_lossy(&data[start .. *pos]).into_owned())
+    };
+
+    let magic = token(&mut pos)?;
+    let (channels, binary) = match magic.as_str() {
+        "P2" => (1, false),
+        "P3" => (3, false),
+        "P5" => (1, true),
+        "P6" => (3, true),
+        _ => {
+            return Err(From::from(format!("unsupported image type {}", magic)))
+        },
+    };
+    let w: Dimension = token(&mut pos)?.parse()?;
+    let h: Dimension = token(&mut pos)?.parse()?;
+    let max_value: u32 = token(&mut pos)?.parse()?;
+    if max_value == 0 || max_value > 0xffff {
+        return Err(From::from(format!("bad maximum value {}", max_value)));
+    }
+
+    // Every sample takes at least a byte, so a header claiming more than
+    // are left is wrong, and shouldn't get that much memory reserved.
+    let num_samples = (w as usize).checked_mul(h as usize)
+        .and_then(|n| n.checked_mul(channels))
+        .filter(|&n| n <= data.len() - pos)
+        .ok_or_else(|| format!("{} by {} image is larger than its data",
+                               w, h))?;
+    let mut samples = Vec::with_capacity(num_samples);
+    if binary {
+        // Exactly one whitespace byte separates the header from the pixels.
+        pos += 1;
+        let width = if max_value > 0xff { 2 } else { 1 };
+        let end = pos + num_samples * width;
+        if data.len() < end {
+            return Err(From::from("image data is truncated"));
+        }
+        for s in data[pos .. end].chunks(width) {
+            let value = s.iter().fold(0u32, |v, &b| (v << 8) | b as u32);
+            samples.push(value as f64 / max_value as f64);
+        }
+    } else {
+        for _ in 0 .. num_samples {
+            let value: u32 = token(&mut pos)?.parse()?;
+            samples.push(value as f64 / max_value as f64);
+        }
+    }
+
+    Ok(Image { w, h, channels, samples })
+}
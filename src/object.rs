@@ -1,32 +1,122 @@
+use std::cell::Cell;
+use std::cell::Ref;
+use std::cell::RefCell;
+use std::cmp::Ordering::Equal;
 use std::error;
 use std::fs;
 use std::io;
 use std::io::BufRead;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
 
+use animation::Pose;
+use bounds::Aabb;
+use bounds::Sphere;
+use bvh::Bvh;
+use format::PixelFormat;
+use gbuffer::NO_ID;
+use mesh::PolyMesh;
+use renderer;
 use renderer::Renderer;
 use screen::Screen;
+use simplify;
+use simplify::Target;
+use subdivide;
 use types::*;
 
-// FIXME: Add transformation cacheing.
-#[derive(Clone)]
+// Identifies an Object for as long as it lives. Clones get their own ID.
+pub type ObjectId = u32;
+
+static NEXT_OBJECT_ID: AtomicU32 = AtomicU32::new(0);
+
+fn next_object_id() -> ObjectId {
+    let id = NEXT_OBJECT_ID.fetch_add(1, Ordering::Relaxed);
+    assert!(id != NO_ID, "ran out of object IDs");
+    id
+}
+
+// Where a ray hit an Object, in world space.
+#[derive(Clone, Copy, Debug)]
+pub struct ObjectHit {
+    pub t: Coord,
+    pub triangle_index: u32,
+    pub position: Point,
+}
+
 pub struct Object {
+    id: ObjectId,
+
     translation: Transform,
-    rotation:    Transform,
+    rotation:    Quaternion,
     scaling:     Transform,
+    // The three above combined, kept up to date as they change.
+    world:       Transform,
     triangles:   Vec<Triangle>,
+
+    // Optional per-vertex attributes, one entry per triangle, in object
+    // space.
+    normals: Option<Vec<[Point; 3]>>,
+    uvs:     Option<Vec<[TexCoord; 3]>>,
+
+    // Bounds of the triangles, untransformed and in world space.
+    local_bounds: Aabb,
+    bounds:       Aabb,
+    sphere:       Sphere,
+
+    // Hierarchy over the triangles in world space, if one has been built.
+    // Moving the object only marks it stale; it's refit when next used.
+    bvh: Option<RefCell<Bvh>>,
+    bvh_stale: Cell<bool>,
 }
 
 #[allow(dead_code)]
 impl Object {
     pub fn new(tris: Vec<Triangle>) -> Object {
+        let local_bounds = Aabb::from_triangles(&tris);
         Object {
+            id: next_object_id(),
+
             translation: Transform::identity(),
-            rotation:    Transform::identity(),
+            rotation:    Quaternion::identity(),
             scaling:     Transform::identity(),
+            world:       Transform::identity(),
             triangles: tris,
+
+            normals: None,
+            uvs:     None,
+
+            local_bounds,
+            bounds: local_bounds,
+            sphere: local_bounds.bounding_sphere(),
+
+            bvh: None,
+            bvh_stale: Cell::new(false),
         }
     }
 
+    // Makes an object with vertex normals and texture coordinates, given for
+    // each vertex of each triangle.
+    pub fn with_attributes(
+        tris: Vec<Triangle>,
+        normals: Vec<[Point; 3]>,
+        uvs: Vec<[TexCoord; 3]>,
+    ) -> Object {
+        assert!(normals.len() == tris.len() && uvs.len() == tris.len());
+        let mut object = Object::new(tris);
+        object.normals = Some(normals);
+        object.uvs     = Some(uvs);
+        object
+    }
+
+    pub fn with_normals(tris: Vec<Triangle>, normals: Vec<[Point; 3]>)
+        -> Object
+    {
+        assert!(normals.len() == tris.len());
+        let mut object = Object::new(tris);
+        object.normals = Some(normals);
+        object
+    }
+
     pub fn from_file(filename: &str) -> Result<Object, Box<error::Error>> {
         let f = try!(fs::File::open(filename));
         let reader = io::BufReader::new(f);
@@ -72,72 +162,457 @@ impl Object {
         }
     }
 
-    pub fn render<S: Screen>(&self, renderer: &mut Renderer<S>) {
+    pub fn id(&self) -> ObjectId { self.id }
+
+    pub fn triangles(&self) -> &[Triangle] { &self.triangles }
+
+    pub fn normals(&self) -> Option<&[[Point; 3]]> {
+        self.normals.as_ref().map(|n| &n[..])
+    }
+
+    pub fn uvs(&self) -> Option<&[[TexCoord; 3]]> {
+        self.uvs.as_ref().map(|uv| &uv[..])
+    }
+
+    // Replaces the triangles with as many others, such as a deformed copy
+    // of them, keeping the transforms and texture coordinates. The normals
+    // are replaced with the given ones, if any.
+    pub fn set_triangles(
+        &mut self,
+        tris: Vec<Triangle>,
+        normals: Option<Vec<[Point; 3]>>,
+    ) {
+        assert!(tris.len() == self.triangles.len());
+        if let Some(ref n) = normals { assert!(n.len() == tris.len()) }
+        self.triangles = tris;
+        self.normals = normals;
+        self.local_bounds = Aabb::from_triangles(&self.triangles);
+        self.update_bounds();
+    }
+
+    // Returns a new object with this one's transforms and other triangles.
+    // Vertex attributes are not carried over.
+    fn with_triangles(&self, tris: Vec<Triangle>) -> Object {
+        let mut object = Object::new(tris);
+        object.translation = self.translation;
+        object.rotation    = self.rotation;
+        object.scaling     = self.scaling;
+        object.update_bounds();
+        if self.bvh.is_some() { object.build_bvh() }
+        object
+    }
+
+    // Returns a copy smoothed by Loop subdivision. Edges where faces meet at
+    // more than crease_angle radians are kept sharp.
+    pub fn loop_subdivided(&self, levels: usize, crease_angle: Coord)
+        -> Object
+    {
+        let mesh = PolyMesh::from_triangles(&self.triangles);
+        let creases = subdivide::creases_by_angle(&mesh, crease_angle);
+        self.with_triangles(
+            subdivide::loop_subdivide(&mesh, levels, &creases).to_triangles()
+        )
+    }
+
+    // Returns a copy smoothed by Catmull–Clark subdivision, after joining
+    // triangle pairs back into quads. Creases are as for loop_subdivided.
+    pub fn catmull_clark_subdivided(&self, levels: usize, crease_angle: Coord)
+        -> Object
+    {
+        let mesh = PolyMesh::from_triangles_merging_quads(&self.triangles);
+        let creases = subdivide::creases_by_angle(&mesh, crease_angle);
+        self.with_triangles(
+            subdivide::catmull_clark(&mesh, levels, &creases).to_triangles()
+        )
+    }
+
+    // Returns a copy with fewer triangles, made by quadric edge collapse.
+    pub fn simplified(&self, target: Target) -> Object {
+        self.with_triangles(
+            simplify::simplify_triangles(&self.triangles, target)
+        )
+    }
+
+    pub fn render<S: Screen, P: PixelFormat>(
+        &self,
+        renderer: &mut Renderer<S, P>,
+    ) {
+        if !renderer.is_visible(&self.sphere, &self.bounds) { return }
+
         let world_transform = self.world_transform();
-        for t in &self.triangles {
-            renderer.fill_triangle(*t * world_transform);
+        let visible = self.visible_triangles(renderer, Transform::identity());
+        renderer.set_object_id(self.id);
+        for i in visible {
+            let world = self.triangles[i] * world_transform;
+            renderer.fill_mesh_triangle(world, world, i as u32);
         }
+        renderer.set_object_id(NO_ID);
     }
 
-    pub fn render_with_transform<S: Screen>(
+    // The indices of the triangles in parts of the hierarchy in view, or of
+    // all of them without one. `to_view` takes world space to view space.
+    fn visible_triangles<S: Screen, P: PixelFormat>(
         &self,
-        renderer: &mut Renderer<S>,
+        renderer: &Renderer<S, P>,
+        to_view: Transform,
+    ) -> Vec<usize> {
+        let bvh = match self.bvh() {
+            Some(bvh) => bvh,
+            None => return (0 .. self.triangles.len()).collect(),
+        };
+        let mut visible = Vec::new();
+        bvh.visit(
+            |b| {
+                let b = b.transformed(to_view);
+                renderer.is_visible(&b.bounding_sphere(), &b)
+            },
+            |i| visible.push(i),
+        );
+        visible
+    }
+
+    pub fn render_with_transform<S: Screen, P: PixelFormat>(
+        &self,
+        renderer: &mut Renderer<S, P>,
         transform: Transform,
     ) {
-        let camera_transform = transform * self.world_transform();
-        for t in &self.triangles {
-            renderer.fill_triangle(*t * camera_transform);
+        self.render_in(renderer, Transform::identity(), transform);
+    }
+
+    // Renders the object as the child of something with the world transform
+    // `parent`, as seen through the view transform `view`.
+    pub fn render_in<S: Screen, P: PixelFormat>(
+        &self,
+        renderer: &mut Renderer<S, P>,
+        parent: Transform,
+        view: Transform,
+    ) {
+        let bounds = self.bounds.transformed(view * parent);
+        if !renderer.is_visible(&bounds.bounding_sphere(), &bounds) { return }
+
+        let world_transform = parent * self.world_transform();
+        let camera_transform = view * world_transform;
+        let visible = self.visible_triangles(renderer, view * parent);
+        renderer.set_object_id(self.id);
+        for i in visible {
+            let t = self.triangles[i];
+            renderer.fill_mesh_triangle(
+                t * camera_transform,
+                t * world_transform,
+                i as u32,
+            );
         }
+        renderer.set_object_id(NO_ID);
     }
 
-    fn world_transform(&self) -> Transform {
-        self.translation * self.rotation * self.scaling
+    // Like render_in, but draws the triangles farthest from the eye first,
+    // for translucent objects.
+    pub fn render_sorted_in<S: Screen, P: PixelFormat>(
+        &self,
+        renderer: &mut Renderer<S, P>,
+        parent: Transform,
+        view: Transform,
+    ) {
+        let bounds = self.bounds.transformed(view * parent);
+        if !renderer.is_visible(&bounds.bounding_sphere(), &bounds) { return }
+
+        let world_transform = parent * self.world_transform();
+        let camera_transform = view * world_transform;
+        let visible = self.visible_triangles(renderer, view * parent);
+        let view_triangles: Vec<Triangle> = visible.iter()
+            .map(|&i| self.triangles[i] * camera_transform)
+            .collect();
+        renderer.set_object_id(self.id);
+        for j in renderer::back_to_front(&view_triangles) {
+            let i = visible[j];
+            renderer.fill_mesh_triangle(
+                view_triangles[j],
+                self.triangles[i] * world_transform,
+                i as u32,
+            );
+        }
+        renderer.set_object_id(NO_ID);
+    }
+
+    fn world_transform(&self) -> Transform { self.world }
+
+    // World-space bounds, kept up to date as the object is transformed.
+    pub fn bounds(&self) -> Aabb { self.bounds }
+    pub fn bounding_sphere(&self) -> Sphere { self.sphere }
+
+    fn update_bounds(&mut self) {
+        self.world =
+            self.translation * self.rotation.to_transform() * self.scaling;
+        self.bounds = self.local_bounds.transformed(self.world_transform());
+        self.sphere = self.bounds.bounding_sphere();
+        self.bvh_stale.set(true);
+    }
+
+    fn world_triangle_bounds(&self) -> Vec<Aabb> {
+        self.world_triangles()
+            .map(|t| Aabb::from_points(t.to_arr().iter().cloned()))
+            .collect()
+    }
+
+    // Builds a hierarchy over the triangles, which speeds up culling and ray
+    // queries on large meshes. It is refit when used after the object has
+    // been transformed.
+    pub fn build_bvh(&mut self) {
+        let bvh = Bvh::build(&self.world_triangle_bounds());
+        self.bvh = Some(RefCell::new(bvh));
+        self.bvh_stale.set(false);
+    }
+
+    fn bvh(&self) -> Option<Ref<'_, Bvh>> {
+        let bvh = self.bvh.as_ref()?;
+        if self.bvh_stale.get() {
+            bvh.borrow_mut().refit(&self.world_triangle_bounds());
+            self.bvh_stale.set(false);
+        }
+        Some(bvh.borrow())
+    }
+
+    pub fn with_bvh(mut self) -> Object {
+        self.build_bvh();
+        self
+    }
+
+    fn world_triangles<'a>(&'a self) -> impl Iterator<Item = Triangle> + 'a {
+        let world_transform = self.world_transform();
+        self.triangles.iter().map(move |t| *t * world_transform)
+    }
+
+
+    // Returns the nearest hit of a world-space ray on this object.
+    pub fn intersect_ray(&self, ray: Ray) -> Option<ObjectHit> {
+        if let Some(bvh) = self.bvh() {
+            let world_transform = self.world_transform();
+            return bvh
+                .intersect_ray(ray, |i| {
+                    (self.triangles[i] * world_transform)
+                        .intersect_ray(ray)
+                        .map(|hit| hit.t)
+                })
+                .map(|(i, t)| ObjectHit {
+                    t,
+                    triangle_index: i as u32,
+                    position: ray.at(t),
+                });
+        }
+
+        self.world_triangles()
+            .enumerate()
+            .filter_map(|(i, t)| t.intersect_ray(ray).map(|hit| (i, hit)))
+            .min_by(|(_, a), (_, b)| a.t.partial_cmp(&b.t).unwrap_or(Equal))
+            .map(|(i, hit)| ObjectHit {
+                t: hit.t,
+                triangle_index: i as u32,
+                position: ray.at(hit.t),
+            })
+    }
+
+    // Returns true if the segment between two world points crosses this
+    // object, for line-of-sight checks. Hits right at `from` are taken to be
+    // the surface it lies on, so a point on the object can see past it.
+    pub fn blocks(&self, from: Point, to: Point) -> bool {
+        let ray = Ray::between(from, to);
+        let min_t = Coord::EPSILON.sqrt();
+        let hits = |t: Triangle| {
+            t.intersect_ray(ray).is_some_and(|hit| hit.t > min_t && hit.t <= 1.)
+        };
+        let bvh = match self.bvh() {
+            Some(bvh) => bvh,
+            None => return self.world_triangles().any(hits),
+        };
+
+        // Only test triangles in boxes the segment passes through.
+        let mut crossed = Vec::new();
+        bvh.visit(
+            |b| b.intersect_ray(ray).is_some_and(|(t_enter, _)| t_enter <= 1.),
+            |i| crossed.push(i),
+        );
+        let world_transform = self.world_transform();
+        crossed.into_iter().any(|i| hits(self.triangles[i] * world_transform))
+    }
+
+    // Returns the point on this object's surface nearest to a world point,
+    // or None if the object has no triangles.
+    pub fn closest_point(&self, p: Point) -> Option<Point> {
+        self.world_triangles()
+            .map(|t| t.closest_point(p))
+            .min_by(|a, b| {
+                (*a - p).magnitude()
+                    .partial_cmp(&(*b - p).magnitude())
+                    .unwrap_or(Equal)
+            })
     }
 
 
     pub fn translate(&mut self, off: Point) {
         self.translation = Transform::translate(off) * self.translation;
+        self.update_bounds();
     }
 
-    pub fn scale(&mut self, x: f64, y: f64, z: f64) {
+    pub fn scale(&mut self, x: Coord, y: Coord, z: Coord) {
         self.scaling = Transform::scale(x, y, z) * self.scaling;
+        self.update_bounds();
+    }
+
+    pub fn rotate_x(&mut self, theta: Coord) {
+        self.rotate(Quaternion::rotate_x(theta));
+    }
+
+    pub fn rotate_y(&mut self, theta: Coord) {
+        self.rotate(Quaternion::rotate_y(theta));
+    }
+
+    pub fn rotate_z(&mut self, theta: Coord) {
+        self.rotate(Quaternion::rotate_z(theta));
+    }
+
+    pub fn rotate(&mut self, rotation: Quaternion) {
+        self.rotation = (rotation * self.rotation).normalized();
+        self.update_bounds();
+    }
+
+    pub fn rotation(&self) -> Quaternion { self.rotation }
+
+    pub fn set_rotation(&mut self, rotation: Quaternion) {
+        self.rotation = rotation.normalized();
+        self.update_bounds();
+    }
+
+    pub fn translation(&self) -> Point {
+        let m = &self.translation.data;
+        pt![m[0][DIM], m[1][DIM], m[2][DIM]]
     }
 
-    pub fn rotate_x(&mut self, theta: f64) {
-        self.rotation = Transform::rotate_x(theta) * self.rotation;
+    pub fn scaling(&self) -> Point {
+        let m = &self.scaling.data;
+        pt![m[0][0], m[1][1], m[2][2]]
     }
 
-    pub fn rotate_y(&mut self, theta: f64) {
-        self.rotation = Transform::rotate_y(theta) * self.rotation;
+    pub fn pose(&self) -> Pose {
+        Pose {
+            translation: self.translation(),
+            rotation: self.rotation,
+            scale: self.scaling(),
+        }
     }
 
-    pub fn rotate_z(&mut self, theta: f64) {
-        self.rotation = Transform::rotate_z(theta) * self.rotation;
+    // Replaces the translation, rotation and scale all at once.
+    pub fn set_pose(&mut self, pose: &Pose) {
+        let s = pose.scale;
+        self.translation = Transform::translate(pose.translation);
+        self.rotation = pose.rotation.normalized();
+        self.scaling = Transform::scale(s.x, s.y, s.z);
+        self.update_bounds();
     }
 
 
     pub fn translated(mut self, off: Point) -> Object {
         self.translation = Transform::translate(off) * self.translation;
+        self.update_bounds();
         self
     }
 
-    pub fn scaled(mut self, x: f64, y: f64, z: f64) -> Object {
+    pub fn scaled(mut self, x: Coord, y: Coord, z: Coord) -> Object {
         self.scaling = Transform::scale(x, y, z) * self.scaling;
+        self.update_bounds();
         self
     }
 
-    pub fn rotated_x(mut self, theta: f64) -> Object {
-        self.rotation = Transform::rotate_x(theta) * self.rotation;
+    pub fn rotated_x(mut self, theta: Coord) -> Object {
+        self.rotate(Quaternion::rotate_x(theta));
         self
     }
 
-    pub fn rotated_y(mut self, theta: f64) -> Object {
-        self.rotation = Transform::rotate_y(theta) * self.rotation;
+    pub fn rotated_y(mut self, theta: Coord) -> Object {
+        self.rotate(Quaternion::rotate_y(theta));
         self
     }
 
-    pub fn rotated_z(mut self, theta: f64) -> Object {
-        self.rotation = Transform::rotate_z(theta) * self.rotation;
+    pub fn rotated_z(mut self, theta: Coord) -> Object {
+        self.rotate(Quaternion::rotate_z(theta));
         self
     }
+
+    pub fn rotated(mut self, rotation: Quaternion) -> Object {
+        self.rotate(rotation);
+        self
+    }
+
+    // Makes this object pick as another, e.g. a coarser version of it.
+    pub fn with_id(mut self, id: ObjectId) -> Object {
+        self.id = id;
+        self
+    }
+}
+
+impl Clone for Object {
+    fn clone(&self) -> Object {
+        Object {
+            id: next_object_id(),
+
+            translation: self.translation,
+            rotation:    self.rotation,
+            scaling:     self.scaling,
+            world:       self.world,
+            triangles:   self.triangles.clone(),
+
+            normals: self.normals.clone(),
+            uvs:     self.uvs.clone(),
+
+            local_bounds: self.local_bounds,
+            bounds:       self.bounds,
+            sphere:       self.sphere,
+
+            bvh: self.bvh.clone(),
+            bvh_stale: self.bvh_stale.clone(),
+        }
+    }
+}
+
+
+// Builds a hierarchy over a set of objects by their world bounds. Rebuild or
+// refit it with object_bounds when they move.
+pub fn build_bvh(objects: &[Object]) -> Bvh {
+    Bvh::build(&object_bounds(objects))
+}
+
+pub fn object_bounds(objects: &[Object]) -> Vec<Aabb> {
+    objects.iter().map(|o| o.bounds()).collect()
+}
+
+// Returns the index of the nearest object hit by a world-space ray, and
+// where it was hit.
+pub fn intersect_objects(objects: &[Object], bvh: &Bvh, ray: Ray)
+    -> Option<(usize, ObjectHit)>
+{
+    let mut nearest: Option<(usize, ObjectHit)> = None;
+    bvh.intersect_ray(ray, |i| {
+        let hit = objects[i].intersect_ray(ray)?;
+        if hit.t < nearest.map_or(Coord::INFINITY, |(_, best)| best.t) {
+            nearest = Some((i, hit));
+        }
+        Some(hit.t)
+    });
+    nearest
+}
+
+// Renders the objects, skipping whole branches of the hierarchy that are out
+// of view.
+pub fn render_objects<S: Screen, P: PixelFormat>(
+    objects: &[Object],
+    bvh: &Bvh,
+    renderer: &mut Renderer<S, P>,
+) {
+    let mut visible = Vec::new();
+    bvh.visit(
+        |b| renderer.is_visible(&b.bounding_sphere(), b),
+        |i| visible.push(i),
+    );
+    for i in visible {
+        objects[i].render(renderer);
+    }
 }
@@ -1,5 +1,10 @@
 #![allow(dead_code)]
 
+use std::ops;
+
+use types::Coord;
+use utils::clamp;
+
 pub const BLACK: Pixel = (0x00, 0x00, 0x00);
 pub const WHITE: Pixel = (0xff, 0xff, 0xff);
 pub const RED:   Pixel = (0xff, 0x00, 0x00);
@@ -8,13 +13,188 @@ pub const BLUE:  Pixel = (0x00, 0x00, 0xff);
 
 pub type Pixel = (u8, u8, u8); // RGB
 
+// Darkest to brightest. Enough steps that depth cueing shows up as a
+// gradient rather than a hard cutoff.
+const ASCII_RAMP: &[u8] = b" .:-=+*#%X";
+
 pub fn as_char(p: Pixel) -> char {
-    // FIXME: Improve value formula.
     let (r, g, b) = p;
-    let value = r as u16 + g as u16 + b as u16;
+    let value = r as usize + g as usize + b as usize;
+    let max_value = 3 * 0xff + 1;
+
+    ASCII_RAMP[value * ASCII_RAMP.len() / max_value] as char
+}
+
+// Linearly interpolates from `a` at t = 0 to `b` at t = 1.
+pub fn lerp(a: Pixel, b: Pixel, t: Coord) -> Pixel {
+    let mix = |a: u8, b: u8| {
+        (a as Coord + (b as Coord - a as Coord) * t) as u8
+    };
+    (mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2))
+}
+
+// Converts an sRGB-encoded channel to linear light in [0, 1].
+pub fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.;
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+// Converts linear light to an sRGB-encoded channel, clamping to [0, 1].
+pub fn linear_to_srgb(c: f32) -> u8 {
+    let c = clamp(c, 0., 1.);
+    let encoded = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    };
+    (encoded * 255.).round() as u8
+}
+
+// A linear, high dynamic range color. Channels may go above 1, e.g. where
+// several lights add up; they are only squeezed into a Pixel by tone
+// mapping on the way to the screen.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+}
+
+impl Color {
+    pub fn new(r: f32, g: f32, b: f32) -> Color { Color { r, g, b } }
+
+    pub fn black() -> Color { Color::new(0., 0., 0.) }
+
+    // Decodes an sRGB pixel, as colors given by users and read from images
+    // are.
+    pub fn from_srgb(p: Pixel) -> Color {
+        Color::new(srgb_to_linear(p.0), srgb_to_linear(p.1),
+                   srgb_to_linear(p.2))
+    }
+
+    // Clamps and encodes as sRGB, without tone mapping.
+    pub fn to_srgb(self) -> Pixel {
+        (linear_to_srgb(self.r), linear_to_srgb(self.g),
+         linear_to_srgb(self.b))
+    }
+
+    pub fn luminance(self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn map<F: Fn(f32) -> f32>(self, f: F) -> Color {
+        Color::new(f(self.r), f(self.g), f(self.b))
+    }
+
+    // Linearly interpolates from `self` at t = 0 to `other` at t = 1.
+    pub fn lerp(self, other: Color, t: f32) -> Color {
+        self * (1. - t) + other * t
+    }
+}
+
+impl From<Pixel> for Color {
+    fn from(p: Pixel) -> Color { Color::from_srgb(p) }
+}
+
+impl ops::Add for Color {
+    type Output = Color;
+    fn add(self, other: Color) -> Color {
+        Color::new(self.r + other.r, self.g + other.g, self.b + other.b)
+    }
+}
+
+impl ops::Mul for Color {
+    type Output = Color;
+    fn mul(self, other: Color) -> Color {
+        Color::new(self.r * other.r, self.g * other.g, self.b * other.b)
+    }
+}
+
+impl ops::Mul<f32> for Color {
+    type Output = Color;
+    fn mul(self, k: f32) -> Color {
+        Color::new(self.r * k, self.g * k, self.b * k)
+    }
+}
+
+// A linear color with an opacity, from 0 for clear to 1 for opaque. The
+// color isn't scaled by alpha, except when drawn with
+// BlendMode::Premultiplied.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rgba {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Rgba {
+    pub fn new(r: f32, g: f32, b: f32, a: f32) -> Rgba { Rgba { r, g, b, a } }
+
+    pub fn from_color(c: Color, a: f32) -> Rgba {
+        Rgba { r: c.r, g: c.g, b: c.b, a }
+    }
+
+    // Decodes an sRGB pixel, with an alpha from 0 to 255.
+    pub fn from_pixel(p: Pixel, a: u8) -> Rgba {
+        Rgba::from_color(Color::from_srgb(p), a as f32 / 255.)
+    }
+
+    // Drops the alpha.
+    pub fn color(self) -> Color { Color::new(self.r, self.g, self.b) }
+
+    pub fn premultiplied(self) -> Rgba {
+        Rgba::from_color(self.color() * self.a, self.a)
+    }
+}
+
+impl From<Color> for Rgba {
+    fn from(c: Color) -> Rgba { Rgba::from_color(c, 1.) }
+}
+
+impl From<Pixel> for Rgba {
+    fn from(p: Pixel) -> Rgba { Rgba::from_pixel(p, 0xff) }
+}
+
+// How a color being drawn combines with the one already there. Blending
+// happens in linear light.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    // Ignores alpha and overwrites.
+    Replace,
+    // Mixes by alpha: src * a + dst * (1 - a).
+    Alpha,
+    // Adds the color, scaled by alpha, e.g. for glows and particles.
+    Additive,
+    // Darkens by the color, as far as alpha says, e.g. for tinted glass.
+    Multiply,
+    // Like Alpha, for colors already scaled by their alpha: src + dst *
+    // (1 - a).
+    Premultiplied,
+}
+
+impl BlendMode {
+    pub fn blend(&self, src: Rgba, dst: Color) -> Color {
+        let a = src.a;
+        let src = src.color();
+        match *self {
+            BlendMode::Replace => src,
+            BlendMode::Alpha => src * a + dst * (1. - a),
+            BlendMode::Additive => dst + src * a,
+            BlendMode::Multiply => dst * src.map(|c| c * a + 1. - a),
+            BlendMode::Premultiplied => src + dst * (1. - a),
+        }
+    }
 
-    if      value > 0x240 { 'X' }
-    else if value > 0x180 { 'x' }
-    else if value > 0x0c0 { '.' }
-    else                  { ' ' }
+    // Blends onto a destination with its own alpha, which builds up as
+    // translucent layers cover it.
+    pub fn blend_rgba(&self, src: Rgba, dst: Rgba) -> Rgba {
+        let alpha = match *self {
+            BlendMode::Replace => src.a,
+            BlendMode::Alpha | BlendMode::Premultiplied =>
+                src.a + dst.a * (1. - src.a),
+            BlendMode::Additive | BlendMode::Multiply => dst.a,
+        };
+        Rgba::from_color(self.blend(src, dst.color()), alpha)
+    }
 }
@@ -0,0 +1,294 @@
+// Generators for common shapes. Each is centered on the origin, with
+// outward-facing triangles, vertex normals and texture coordinates.
+
+use std::collections::HashMap;
+use std::mem;
+
+use object::Object;
+use types::coord::consts::PI;
+use types::*;
+use utils::clamp;
+
+
+#[derive(Clone, Copy)]
+struct Vertex {
+    position: Point,
+    normal:   Point,
+    uv:       TexCoord,
+}
+
+fn vertex(position: Point, normal: Point, uv: TexCoord) -> Vertex {
+    Vertex { position, normal, uv }
+}
+
+#[derive(Default)]
+struct Builder {
+    triangles: Vec<Triangle>,
+    normals:   Vec<[Point; 3]>,
+    uvs:       Vec<[TexCoord; 3]>,
+}
+
+impl Builder {
+    // Adds a triangle wound to face the way its vertex normals point.
+    // Triangles with no area, like those at the poles of a sphere, are
+    // dropped.
+    fn triangle(&mut self, a: Vertex, mut b: Vertex, mut c: Vertex) {
+        let t = trigon![a.position, b.position, c.position];
+        let face = (t.p2 - t.p1).cross(t.p3 - t.p1);
+        if face.magnitude() < 1e-12 { return }
+        if face.dot(a.normal + b.normal + c.normal) < 0. {
+            mem::swap(&mut b, &mut c);
+        }
+
+        self.triangles.push(trigon![a.position, b.position, c.position]);
+        self.normals.push([a.normal, b.normal, c.normal]);
+        self.uvs.push([a.uv, b.uv, c.uv]);
+    }
+
+    // Adds a grid of quads from nu + 1 by nv + 1 vertices.
+    fn grid<F>(&mut self, nu: usize, nv: usize, f: F)
+        where F: Fn(usize, usize) -> Vertex
+    {
+        for i in 0 .. nu {
+            for j in 0 .. nv {
+                let (a, b) = (f(i, j),     f(i + 1, j));
+                let (c, d) = (f(i + 1, j + 1), f(i, j + 1));
+                self.triangle(a, b, c);
+                self.triangle(a, c, d);
+            }
+        }
+    }
+
+    // Spins a profile around the y axis. Each profile point is a radius,
+    // a height, and the normal's radial and vertical parts.
+    fn lathe(
+        &mut self,
+        segments: usize,
+        profile: &[(Coord, Coord, Coord, Coord)],
+    ) {
+        let segments = segments.max(3);
+        let rings = profile.len() - 1;
+        self.grid(segments, rings, |i, j| {
+            let u = i as Coord / segments as Coord;
+            let (r, y, nr, ny) = profile[j];
+            // Close the seam exactly, so that its vertices weld.
+            let angle = (i % segments) as Coord / segments as Coord;
+            let (sin, cos) = (2. * PI * angle).sin_cos();
+            vertex(
+                pt![r * cos, y, r * sin],
+                pt![nr * cos, ny, nr * sin].normalized(),
+                (u, j as Coord / rings as Coord),
+            )
+        });
+    }
+
+    fn build(self) -> Object {
+        Object::with_attributes(self.triangles, self.normals, self.uvs)
+    }
+}
+
+// A box with the given side lengths, each face split into a grid.
+pub fn cuboid(w: Coord, h: Coord, d: Coord, segments: usize) -> Object {
+    let n = segments.max(1);
+    let half = pt![w / 2., h / 2., d / 2.];
+    let mut b = Builder::default();
+
+    // Each face as its normal and two axes spanning it.
+    let faces = [
+        (pt![ 1.,  0.,  0.], pt![0., 0., -1.], pt![0., 1., 0.]),
+        (pt![-1.,  0.,  0.], pt![0., 0.,  1.], pt![0., 1., 0.]),
+        (pt![ 0.,  1.,  0.], pt![1., 0.,  0.], pt![0., 0., -1.]),
+        (pt![ 0., -1.,  0.], pt![1., 0.,  0.], pt![0., 0.,  1.]),
+        (pt![ 0.,  0.,  1.], pt![1., 0.,  0.], pt![0., 1., 0.]),
+        (pt![ 0.,  0., -1.], pt![-1., 0., 0.], pt![0., 1., 0.]),
+    ];
+    let scale = |p: Point| pt![p.x * half.x, p.y * half.y, p.z * half.z];
+    for &(normal, u_axis, v_axis) in &faces {
+        b.grid(n, n, |i, j| {
+            let (u, v) = (i as Coord / n as Coord, j as Coord / n as Coord);
+            let p = normal + u_axis * (2. * u - 1.) + v_axis * (2. * v - 1.);
+            vertex(scale(p), normal, (u, v))
+        });
+    }
+    b.build()
+}
+
+// A sphere split into segments around its equator and rings from pole to
+// pole.
+pub fn uv_sphere(radius: Coord, segments: usize, rings: usize) -> Object {
+    let rings = rings.max(2);
+    let profile: Vec<_> = (0 .. rings + 1)
+        .map(|j| {
+            let theta = PI * j as Coord / rings as Coord;
+            let (sin, cos) = theta.sin_cos();
+            // Meet exactly at the poles.
+            let sin = if j == 0 || j == rings { 0. } else { sin };
+            (radius * sin, radius * cos, sin, cos)
+        })
+        .collect();
+    let mut b = Builder::default();
+    b.lathe(segments, &profile);
+    b.build()
+}
+
+// A sphere made by subdividing an icosahedron, for evenly sized triangles.
+pub fn icosphere(radius: Coord, subdivisions: usize) -> Object {
+    let t = (1. + (5. as Coord).sqrt()) / 2.;
+    let mut points: Vec<Point> = vec![
+        pt![-1.,  t,  0.], pt![ 1.,  t,  0.], pt![-1., -t,  0.],
+        pt![ 1., -t,  0.], pt![ 0., -1.,  t], pt![ 0.,  1.,  t],
+        pt![ 0., -1., -t], pt![ 0.,  1., -t], pt![ t,  0., -1.],
+        pt![ t,  0.,  1.], pt![-t,  0., -1.], pt![-t,  0.,  1.],
+    ].into_iter().map(|p| p.normalized()).collect();
+    let mut faces: Vec<[usize; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    for _ in 0 .. subdivisions {
+        let mut midpoints = HashMap::new();
+        let mut midpoint = |a: usize, b: usize, points: &mut Vec<Point>| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                points.push(((points[a] + points[b]) * 0.5).normalized());
+                points.len() - 1
+            })
+        };
+        faces = faces.iter()
+            .flat_map(|&[a, b, c]| {
+                let ab = midpoint(a, b, &mut points);
+                let bc = midpoint(b, c, &mut points);
+                let ca = midpoint(c, a, &mut points);
+                vec![[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    let to_vertex = |n: Point| {
+        let u = 0.5 + n.z.atan2(n.x) / (2. * PI);
+        let v = clamp(n.y, -1., 1.).acos() / PI;
+        vertex(n * radius, n, (u, v))
+    };
+    let mut b = Builder::default();
+    for f in &faces {
+        b.triangle(
+            to_vertex(points[f[0]]),
+            to_vertex(points[f[1]]),
+            to_vertex(points[f[2]]),
+        );
+    }
+    b.build()
+}
+
+// A capped cylinder standing on the y axis.
+pub fn cylinder(radius: Coord, height: Coord, segments: usize, rings: usize)
+    -> Object
+{
+    let rings = rings.max(1);
+    let half = height / 2.;
+    let profile: Vec<_> = (0 .. rings + 1)
+        .map(|j| (radius, half - height * j as Coord / rings as Coord, 1., 0.))
+        .collect();
+    let mut b = Builder::default();
+    b.lathe(segments, &profile);
+    b.lathe(segments, &[(0., half, 0., 1.), (radius, half, 0., 1.)]);
+    b.lathe(segments, &[(radius, -half, 0., -1.), (0., -half, 0., -1.)]);
+    b.build()
+}
+
+// A cone standing on the y axis with its point at the top.
+pub fn cone(radius: Coord, height: Coord, segments: usize, rings: usize)
+    -> Object
+{
+    let rings = rings.max(1);
+    let half = height / 2.;
+    // The side's normal leans up by the slope of the side.
+    let slant = (radius * radius + height * height).sqrt();
+    let (nr, ny) = (height / slant, radius / slant);
+    let profile: Vec<_> = (0 .. rings + 1)
+        .map(|j| {
+            let t = j as Coord / rings as Coord;
+            (radius * t, half - height * t, nr, ny)
+        })
+        .collect();
+    let mut b = Builder::default();
+    b.lathe(segments, &profile);
+    b.lathe(segments, &[(radius, -half, 0., -1.), (0., -half, 0., -1.)]);
+    b.build()
+}
+
+// A torus around the y axis, with the given distance from its center to the
+// middle of the tube, and the tube's radius.
+pub fn torus(
+    major_radius: Coord,
+    minor_radius: Coord,
+    major_segments: usize,
+    minor_segments: usize,
+) -> Object {
+    let minor_segments = minor_segments.max(3);
+    let profile: Vec<_> = (0 .. minor_segments + 1)
+        .map(|j| {
+            let phi = 2. * PI * (j % minor_segments) as Coord
+                / minor_segments as Coord;
+            let (sin, cos) = phi.sin_cos();
+            (major_radius + minor_radius * cos, minor_radius * sin, cos, sin)
+        })
+        .collect();
+    let mut b = Builder::default();
+    b.lathe(major_segments, &profile);
+    b.build()
+}
+
+// A flat grid in the xz plane, facing up the y axis.
+pub fn plane(width: Coord, depth: Coord, x_segments: usize, z_segments: usize)
+    -> Object
+{
+    let (nx, nz) = (x_segments.max(1), z_segments.max(1));
+    let mut b = Builder::default();
+    b.grid(nx, nz, |i, j| {
+        let (u, v) = (i as Coord / nx as Coord, j as Coord / nz as Coord);
+        vertex(
+            pt![width * (u - 0.5), 0., depth * (v - 0.5)],
+            pt![0., 1., 0.],
+            (u, v),
+        )
+    });
+    b.build()
+}
+
+// A flat disc in the xz plane, facing up the y axis.
+pub fn disc(radius: Coord, segments: usize, rings: usize) -> Object {
+    let rings = rings.max(1);
+    let profile: Vec<_> = (0 .. rings + 1)
+        .map(|j| (radius * j as Coord / rings as Coord, 0., 0., 1.))
+        .collect();
+    let mut b = Builder::default();
+    b.lathe(segments, &profile);
+    b.build()
+}
+
+// A cylinder with hemispherical ends, standing on the y axis. The height
+// is of the straight part between the two ends.
+pub fn capsule(radius: Coord, height: Coord, segments: usize, rings: usize)
+    -> Object
+{
+    // Rings per hemisphere.
+    let rings = rings.max(1);
+    let half = height / 2.;
+    let mut profile = Vec::with_capacity(2 * rings + 2);
+    for j in 0 .. rings + 1 {
+        let theta = PI / 2. * j as Coord / rings as Coord;
+        let (sin, cos) = theta.sin_cos();
+        profile.push((radius * sin, half + radius * cos, sin, cos));
+    }
+    for j in 0 .. rings + 1 {
+        let theta = PI / 2. * (1. + j as Coord / rings as Coord);
+        let (sin, cos) = theta.sin_cos();
+        let sin = if j == rings { 0. } else { sin };
+        profile.push((radius * sin, -half + radius * cos, sin, cos));
+    }
+    let mut b = Builder::default();
+    b.lathe(segments, &profile);
+    b.build()
+}
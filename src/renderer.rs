@@ -1,13 +1,34 @@
+use std::cell::Cell;
 use std::cmp::Ordering::Equal;
 use std::error;
-use std::f64;
 use std::mem;
 
+use antialias;
+use antialias::Antialiasing;
+use antialias::MultisampleBuffer;
+use bounds::Aabb;
+use bounds::Frustum;
+use bounds::Sphere;
+use depth::DepthView;
+use format::PixelFormat;
+use gbuffer::GBuffer;
+use gbuffer::NO_ID;
+use gbuffer::Surface;
+use object::ObjectId;
 use pixel;
-use pixel::Pixel;
+use pixel::BlendMode;
+use pixel::Color;
+use pixel::Rgba;
+use scene::NodeContent;
+use scene::Scene;
 use screen::Screen;
 use texture::Texture;
+use tiles::SetupTriangle;
+use tiles::Shading;
+use tiles::TileRasterizer;
+use tonemap::ToneMapping;
 use types::*;
+use utils::clamp;
 
 
 macro_rules! do_with_color {
@@ -20,29 +41,102 @@ macro_rules! do_with_color {
 }
 
 
+// View-space depth in front of the eye below which objects are culled.
+pub const DEFAULT_NEAR_CLIP: Coord = 1e-3;
+
 pub enum LightingMode {
     NoShading,
     FlatShading,
 }
 
-pub struct Renderer<S>
-    where S: Screen
+// Fog is computed from view-space depth, i.e. the distance along -z of a
+// point before the renderer's transform is applied.
+#[derive(Clone, Copy, Debug)]
+pub enum FogMode {
+    NoFog,
+    Linear { start: Coord, end: Coord },
+    Exponential { density: Coord },
+    ExponentialSquared { density: Coord },
+}
+
+impl FogMode {
+    // Returns the fraction of the surface color left at the given depth,
+    // where 1 is no fog and 0 is fully fogged.
+    pub fn visibility(&self, depth: Coord) -> Coord {
+        let visibility = match *self {
+            FogMode::NoFog => 1.,
+            FogMode::Linear { start, end } => {
+                if end <= start {
+                    if depth < start { 1. } else { 0. }
+                } else {
+                    (end - depth) / (end - start)
+                }
+            },
+            FogMode::Exponential { density } =>
+                (-density * depth).exp(),
+            FogMode::ExponentialSquared { density } =>
+                (-(density * depth) * (density * depth)).exp(),
+        };
+        clamp(visibility, 0., 1.)
+    }
+}
+
+// What was drawn at a screen pixel. Triangles drawn outside of an Object
+// have NO_ID for their object and triangle index.
+#[derive(Clone, Copy, Debug)]
+pub struct Pick {
+    pub object: ObjectId,
+    pub triangle_index: u32,
+    pub depth: Coord,
+    pub position: Point, // World space.
+}
+
+// Draws to a texture of pixel format P, linear Colors unless told otherwise.
+pub struct Renderer<S, P = Color>
+    where S: Screen,
+          P: PixelFormat
 {
     screen: S,
-    texture: Texture,
+    // What's drawn to: the size of the screen, or larger when supersampling.
+    texture: Texture<P>,
+
+    antialiasing: Antialiasing,
+    multisample: Option<MultisampleBuffer>,
+    // The supersampled texture, averaged down to the size of the screen.
+    resolved: Option<Texture<P>>,
+
+    tiles: TileRasterizer,
 
     transform: Transform,
-    color: Pixel,
+    color: Color,
+    alpha: f32,
+    blend_mode: BlendMode,
+    depth_write: bool,
 
-    light: Point,
+    // Positions in view space, and colors, which may be brighter than 1.
+    lights: Vec<(Point, Color)>,
     lighting_mode: LightingMode,
+
+    fog_mode: FogMode,
+    fog_color: Color,
+
+    tone_mapping: ToneMapping,
+
+    object_id: ObjectId,
+
+    frustum_culling: bool,
+    near_clip: Coord,
+    // The frustum of the current transform, built when first needed after
+    // the transform or near clip changes.
+    frustum: Cell<Option<Frustum>>,
 }
 
 #[allow(dead_code)]
-impl<S> Renderer<S>
-    where S: Screen
+impl<S, P> Renderer<S, P>
+    where S: Screen,
+          P: PixelFormat
 {
-    pub fn new(screen: S) -> Renderer<S> {
+    pub fn new(screen: S) -> Renderer<S, P> {
         let w = screen.width();
         let h = screen.height();
 
@@ -50,39 +144,60 @@ impl<S> Renderer<S>
             screen: screen,
             texture: Texture::new(w, h),
 
+            antialiasing: Antialiasing::None,
+            multisample: None,
+            resolved: None,
+
+            tiles: TileRasterizer::new(1),
+
             transform: Transform::identity(),
-            color: pixel::WHITE,
+            color: Color::from_srgb(pixel::WHITE),
+            alpha: 1.,
+            blend_mode: BlendMode::Replace,
+            depth_write: true,
 
-            light: pt![0., 0., 0.],
+            lights: vec![(pt![0., 0., 0.], Color::new(1., 1., 1.))],
             lighting_mode: LightingMode::NoShading,
+
+            fog_mode: FogMode::NoFog,
+            fog_color: Color::black(),
+
+            tone_mapping: ToneMapping::new(),
+
+            object_id: NO_ID,
+
+            frustum_culling: true,
+            near_clip: DEFAULT_NEAR_CLIP,
+            frustum: Cell::new(None),
         }
     }
 
     pub fn draw_point(&mut self, p: Point) {
-        let p = p * self.transform;
-        let d = 7;
+        self.flush_tiles();
+        let p = p * self.raster_transform();
+        let d = 7 * self.supersampling() as PixCoord;
         for row in 0 .. d {
-            self.texture.set_row(
-                p.x as PixCoord - d / 2,
-                p.x as PixCoord + d / 2,
-                p.y as PixCoord + row - d / 2,
-                f64::NEG_INFINITY,
-                f64::NEG_INFINITY,
-                self.color
-            );
+            for col in 0 .. d {
+                self.plot(
+                    p.x as PixCoord + col - d / 2,
+                    p.y as PixCoord + row - d / 2,
+                    Coord::NEG_INFINITY
+                );
+            }
         }
     }
 
     fn draw_point_with_transform(&mut self, p: Point, transform: Transform) {
         let old_transform = self.transform;
-        self.transform = transform;
+        self.set_transform(transform);
         self.draw_point(p);
-        self.transform = old_transform;
+        self.set_transform(old_transform);
     }
 
     pub fn draw_line(&mut self, p1: Point, p2: Point) {
-        let p1 = p1 * self.transform;
-        let p2 = p2 * self.transform;
+        self.flush_tiles();
+        let p1 = p1 * self.raster_transform();
+        let p2 = p2 * self.raster_transform();
         let p1x = p1.x as PixCoord;
         let p1y = p1.y as PixCoord;
         let p2x = p2.x as PixCoord;
@@ -114,12 +229,7 @@ impl<S> Renderer<S>
             }
 
             // FIXME: Do depth lerping.
-            self.texture.set_pixel(
-                x,
-                y,
-                f64::INFINITY,
-                self.color
-            );
+            self.plot(x, y, Coord::INFINITY);
 
             if adx >= ady {
                 if x == p2x { break }
@@ -138,9 +248,9 @@ impl<S> Renderer<S>
         transform: Transform
     ) {
         let old_transform = self.transform;
-        self.transform = transform;
+        self.set_transform(transform);
         self.draw_line(p1, p2);
-        self.transform = old_transform;
+        self.set_transform(old_transform);
     }
 
     pub fn draw_triangle(&mut self, t: Triangle) {
@@ -150,150 +260,526 @@ impl<S> Renderer<S>
     }
 
     pub fn fill_triangle(&mut self, t: Triangle) {
+        self.fill_mesh_triangle(t, t, NO_ID);
+    }
+
+    // Fills a triangle given in view space, recording its view-space normal,
+    // its world-space vertices, and its index within the current object, in
+    // the g-buffer.
+    pub fn fill_mesh_triangle(
+        &mut self,
+        t: Triangle,
+        world: Triangle,
+        index: u32,
+    ) {
         // Backface culling.
         let centroid = (t.p1 + t.p2 + t.p3) * (1. / 3.);
-        let ct = t * self.transform;
+        let ct = t * self.raster_transform();
         if ct.normal().dot(centroid) >= 0. { return }
 
-        // Sort points by y coord.
+        // Rasterize with view-space depth, so that the z-buffer and fog see
+        // distance from the eye.
         let mut pts = ct.to_arr();
-        pts.sort_by(
-            |p1, p2|
-            p1.y.partial_cmp(&p2.y)
-                .unwrap_or(Equal)
-        );
-        let (top, middle, bot) = (pts[0], pts[1], pts[2]);
+        for (p, view) in pts.iter_mut().zip(t.to_arr().iter()) {
+            p.z = -view.z;
+        }
 
-        // Compute color of triangle based on light.
-        let old_color = self.color;
-        self.color = self.light_triangle(t, old_color);
+        let surface = if self.texture.gbuffer.is_some() {
+            Some(Surface::new(
+                self.object_id,
+                index,
+                t.normal(),
+                Triangle::from_arr(pts),
+                world,
+            ))
+        } else {
+            None
+        };
 
-        const EPSILON: f64 = 1.;
-        if middle.y - top.y < EPSILON {
-            self.fill_top_flat_triangle(Triangle::from_arr(pts));
-        } else if bot.y - middle.y < EPSILON {
-            self.fill_bottom_flat_triangle(Triangle::from_arr(pts));
+        let color = self.light_triangle(t, self.color);
+        let multisample = if self.collects_fragments() {
+            None
         } else {
-            let dy_mid: Coord = middle.y - top.y;
-            let dy_bot: Coord = bot.y - top.y;
-            let dx_bot: Coord = bot.x - top.x;
-            let dz_bot: Coord = bot.z - top.z;
-
-            let v4 = pt![
-                top.x + dx_bot * dy_mid / dy_bot,
-                middle.y,
-                top.z + dz_bot * dy_mid / dy_bot
-            ];
-            self.fill_bottom_flat_triangle(trigon![top, middle, v4]);
-            self.fill_top_flat_triangle(trigon![middle, v4, bot]);
+            self.multisample.as_mut()
+        };
+        if let Some(samples) = multisample {
+            let alpha = self.alpha;
+            let fog_mode = self.fog_mode;
+            let fog_color = self.fog_color;
+            let gbuffer = &mut self.texture.gbuffer;
+            samples.fill_triangle(
+                pts,
+                |z| Rgba::from_color(
+                    fog_color.lerp(color, fog_mode.visibility(z) as f32),
+                    alpha,
+                ),
+                |x, y| {
+                    if let (Some(gbuffer), Some(surface)) =
+                        (gbuffer.as_mut(), surface.as_ref())
+                    {
+                        gbuffer.set(x, y, surface);
+                    }
+                },
+            );
+            return;
         }
 
-        self.color = old_color;
+        let triangle = SetupTriangle {
+            pts,
+            shading: Shading {
+                color,
+                alpha: self.alpha,
+                fog_mode: self.fog_mode,
+                fog_color: self.fog_color,
+            },
+            surface,
+            blend_mode: self.blend_mode,
+            depth_write: self.depth_write,
+        };
+        if self.bins_triangles() {
+            self.tiles.push(triangle);
+        } else {
+            triangle.draw(&mut self.texture.rect_mut());
+        }
     }
 
-    fn light_triangle(&self, t: Triangle, color: Pixel) -> Pixel {
+    // Lighting is added up in linear light, so it can go past white, for
+    // tone mapping to deal with.
+    fn light_triangle(&self, t: Triangle, color: Color) -> Color {
         match self.lighting_mode {
             LightingMode::NoShading => color,
             LightingMode::FlatShading => {
                 let centroid = (t.p1 + t.p2 + t.p3) * (1. / 3.);
-                let light_dir = (self.light - centroid).normalized();
-                let light_mag = light_dir.dot(t.normal()).max(0.);
-                let (r, g, b) = color;
-                (
-                    (r as f64 * light_mag) as u8,
-                    (g as f64 * light_mag) as u8,
-                    (b as f64 * light_mag) as u8
-                )
+                let normal = t.normal();
+                self.lights.iter().fold(Color::black(), |sum, &(pos, light)| {
+                    let light_dir = (pos - centroid).normalized();
+                    let light_mag = light_dir.dot(normal).max(0.);
+                    sum + color * light * light_mag as f32
+                })
             },
         }
     }
 
-    fn fill_bottom_flat_triangle(&mut self, t: Triangle) {
-        let (top, mut left, mut right) = t.to_tuple();
-        if left.x > right.x { mem::swap(&mut left, &mut right) }
+    // Draws a pixel in the current color, to every sample of it when
+    // multisampling. Where it writes depth, the g-buffer is emptied, since
+    // there's no surface there to pick.
+    fn plot(&mut self, x: PixCoord, y: PixCoord, z: Coord) {
+        let color = Rgba::from_color(self.color, self.alpha);
+        let collects_fragments = self.collects_fragments();
+        match self.multisample {
+            Some(ref mut samples) if !collects_fragments => {
+                let written = samples.set_pixel(x, y, z, color);
+                if let Some(gbuffer) = self.texture.gbuffer.as_mut() {
+                    if written && self.depth_write { gbuffer.unset(x, y) }
+                }
+            },
+            _ => self.texture.set_pixel(x, y, z, color),
+        }
+    }
 
-        for y in top.y as PixCoord .. left.y as PixCoord {
-            let t = (y - top.y as PixCoord) as Coord / (left.y - top.y);
+    // Whether triangles are kept for the tile rasterizer rather than drawn
+    // straight away. They aren't when multisampling or with an A-buffer,
+    // neither of which can be split between threads.
+    fn bins_triangles(&self) -> bool {
+        self.tiles.threads() > 1
+            && self.multisample.is_none()
+            && self.texture.abuffer.is_none()
+    }
 
-            let z_left  = top.z + t * (left.z  - top.z);
-            let z_right = top.z + t * (right.z - top.z);
+    // Draws the triangles kept for the tile rasterizer.
+    fn flush_tiles(&mut self) {
+        self.tiles.flush(&mut self.texture);
+    }
 
-            self.texture.set_row(
-                (top.x + (left.x  - top.x) * t) as PixCoord,
-                (top.x + (right.x - top.x) * t) as PixCoord,
-                y,
-                z_left,
-                z_right,
-                self.color
-            );
+    // Sets how many threads draw triangles. With more than one, triangles
+    // are kept until the frame is resolved, then drawn a tile at a time in
+    // parallel. The image is the same either way.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.flush_tiles();
+        self.tiles = TileRasterizer::new(threads);
+    }
+
+    pub fn threads(&self) -> usize { self.tiles.threads() }
+
+    // Whether what's drawn now goes to the A-buffer. Fragments are kept per
+    // pixel rather than per sample, so these skip multisampling.
+    fn collects_fragments(&self) -> bool {
+        self.texture.abuffer.is_some() && self.blend_mode != BlendMode::Replace
+    }
+
+    pub fn clear(&mut self) {
+        self.clear_to(pixel::BLACK);
+    }
+
+    pub fn clear_to<C: Into<Rgba>>(&mut self, color: C) {
+        let color = color.into();
+        self.tiles.clear();
+        self.texture.clear_to(color);
+        if let Some(ref mut samples) = self.multisample {
+            samples.clear_to(color);
+        }
+        if let Some(ref mut resolved) = self.resolved {
+            resolved.clear_to(color);
         }
     }
 
-    fn fill_top_flat_triangle(&mut self, t: Triangle) {
-        let (mut left, mut right, bot) = t.to_tuple();
-        if left.x > right.x { mem::swap(&mut left, &mut right) }
+    // Switches antialiasing mode. This reallocates the buffers drawn to, so
+    // whatever has been drawn is lost.
+    pub fn set_antialiasing(&mut self, antialiasing: Antialiasing) {
+        let w = self.screen.width();
+        let h = self.screen.height();
+        let gbuffer = self.texture.gbuffer.is_some();
+        let abuffer = self.texture.abuffer.as_ref().map(|a| a.max_fragments);
+
+        self.tiles.clear();
+        self.multisample = None;
+        self.resolved = None;
+        match antialiasing {
+            Antialiasing::None => {
+                self.texture = Texture::new(w, h);
+            },
+            Antialiasing::Supersample(factor) => {
+                assert!(factor > 0, "can't supersample by 0");
+                self.texture = Texture::new(w * factor, h * factor);
+                self.resolved = Some(Texture::new(w, h));
+            },
+            Antialiasing::Multisample(samples) => {
+                self.texture = Texture::new(w, h);
+                self.multisample =
+                    Some(MultisampleBuffer::new(w, h, samples));
+            },
+        }
+        self.antialiasing = antialiasing;
+        if gbuffer { self.enable_gbuffer() }
+        if let Some(max_fragments) = abuffer {
+            self.enable_abuffer(max_fragments);
+        }
+        let (blend_mode, depth_write) = (self.blend_mode, self.depth_write);
+        self.set_blend_mode(blend_mode);
+        self.set_depth_write(depth_write);
+    }
 
-        for y in left.y as PixCoord .. bot.y as PixCoord + 1 {
-            let t       = (y - left.y as PixCoord) as Coord / (bot.y - left.y);
-            let z_left  = left.z  + t * (bot.z - left.z);
-            let z_right = right.z + t * (bot.z - right.z);
+    pub fn antialiasing(&self) -> Antialiasing { self.antialiasing }
 
-            self.texture.set_row(
-                (left.x  + (bot.x - left.x)  * t) as PixCoord,
-                (right.x + (bot.x - right.x) * t) as PixCoord,
-                y,
-                z_left,
-                z_right,
-                self.color
-            );
+    // How many times larger than the screen the texture drawn to is.
+    fn supersampling(&self) -> Dimension {
+        match self.antialiasing {
+            Antialiasing::Supersample(factor) => factor,
+            _ => 1,
         }
     }
 
-    pub fn clear(&mut self) {
-        self.texture.clear();
+    // The current transform, followed by scaling up to the size of the
+    // texture drawn to.
+    fn raster_transform(&self) -> Transform {
+        match self.supersampling() {
+            1 => self.transform,
+            factor => {
+                let factor = factor as Coord;
+                Transform::scale(factor, factor, 1.) * self.transform
+            },
+        }
+    }
+
+    // Combines the samples and fragments drawn since the last clear into
+    // the final image, which texture, depth_buffer, gbuffer and pick then
+    // read. This also draws triangles kept for the tile rasterizer. Display
+    // resolves by itself.
+    pub fn resolve(&mut self) {
+        self.flush_tiles();
+        if let Some(ref samples) = self.multisample {
+            samples.resolve(&mut self.texture);
+        }
+        self.texture.resolve_abuffer();
+        if let Some(ref mut resolved) = self.resolved {
+            let factor = self.texture.w / resolved.w;
+            antialias::downsample(&self.texture, factor, resolved);
+        }
+    }
+
+    // The texture holding the final image.
+    fn output(&self) -> &Texture<P> {
+        self.resolved.as_ref().unwrap_or(&self.texture)
+    }
+
+    // The final image, for reading back, with any triangles kept for the
+    // tile rasterizer drawn first.
+    fn drawn(&mut self) -> &Texture<P> {
+        self.flush_tiles();
+        self.output()
+    }
+
+    // Draws every mesh in a scene as seen from its camera, lit by all of its
+    // lights in their own colors. A scene without lights keeps the
+    // renderer's.
+    pub fn render_scene(&mut self, scene: &Scene) {
+        let view = scene.view_transform();
+        let lights: Vec<(Point, Color)> = scene.lights()
+            .filter_map(|l| match scene.node(l).content {
+                NodeContent::Light(color) => {
+                    Some((scene.world_position(l) * view, color))
+                },
+                _ => None,
+            })
+            .collect();
+        let old_lights = if lights.is_empty() {
+            None
+        } else {
+            Some(mem::replace(&mut self.lights, lights))
+        };
+        let color = self.color;
+        for (id, node) in scene.nodes().iter().enumerate() {
+            if let NodeContent::Mesh(ref object) = node.content {
+                self.color = node.material.color.into();
+                object.render_in(self, scene.world_transform(id), view);
+            }
+        }
+        self.color = color;
+        if let Some(lights) = old_lights { self.lights = lights }
+    }
+
+    // The view volume of the current transform, in view space.
+    pub fn frustum(&self) -> Frustum {
+        if let Some(frustum) = self.frustum.get() { return frustum }
+        let frustum = Frustum::from_screen_transform(
+            self.transform,
+            self.output().w,
+            self.output().h,
+            self.near_clip,
+        );
+        self.frustum.set(Some(frustum));
+        frustum
+    }
+
+    // Returns false if something with the given view-space bounds can't be
+    // seen, so it can be skipped before any of it is transformed.
+    pub fn is_visible(&self, sphere: &Sphere, bounds: &Aabb) -> bool {
+        if !self.frustum_culling { return true }
+        let frustum = self.frustum();
+        frustum.intersects_sphere(sphere) && frustum.intersects_aabb(bounds)
+    }
+
+    // Returns roughly how many pixels across a view-space sphere appears,
+    // or infinity if the eye is inside it or it is behind the eye.
+    pub fn projected_size(&self, sphere: &Sphere) -> Coord {
+        let depth = -sphere.center.z;
+        if depth <= sphere.radius.max(self.near_clip) { return Coord::INFINITY }
+
+        let center = sphere.center * self.transform;
+        let edge = sphere.center + pt![sphere.radius, 0., 0.];
+        let edge = edge * self.transform;
+        let (dx, dy) = (edge.x - center.x, edge.y - center.y);
+        2. * (dx * dx + dy * dy).sqrt()
+    }
+
+    // With antialiasing, the texture, depth buffer, g-buffer and picking
+    // all show the image as of the last resolve. Triangles kept for the
+    // tile rasterizer are drawn before any of them are read.
+    pub fn texture(&mut self) -> &Texture<P> { self.drawn() }
+
+    // The depth of each pixel drawn since the last clear.
+    pub fn depth_buffer(&mut self) -> DepthView<'_> { self.drawn().depth() }
+
+    pub fn gbuffer(&mut self) -> Option<&GBuffer> {
+        self.drawn().gbuffer.as_ref()
+    }
+
+    pub fn enable_gbuffer(&mut self) {
+        self.flush_tiles();
+        self.texture.enable_gbuffer();
+        if let Some(ref mut resolved) = self.resolved {
+            resolved.enable_gbuffer();
+        }
+    }
+
+    pub fn disable_gbuffer(&mut self) {
+        self.flush_tiles();
+        self.texture.disable_gbuffer();
+        if let Some(ref mut resolved) = self.resolved {
+            resolved.disable_gbuffer();
+        }
+    }
+
+    // Collects pixels drawn with blending into per-pixel lists of at most
+    // `max_fragments`, which are sorted by depth and blended on resolve, so
+    // translucent surfaces needn't be drawn in order. They're still depth
+    // tested against opaque surfaces, which should have depth writes on.
+    pub fn enable_abuffer(&mut self, max_fragments: usize) {
+        self.flush_tiles();
+        self.texture.enable_abuffer(max_fragments);
+    }
+
+    pub fn disable_abuffer(&mut self) { self.texture.disable_abuffer() }
+
+    // Returns what was last drawn at the given pixel, or None if nothing was.
+    // Picking reads the g-buffer, so it must be enabled before rendering.
+    pub fn pick(&mut self, x: Dimension, y: Dimension) -> Option<Pick> {
+        let output = self.drawn();
+        let gbuffer = output.gbuffer.as_ref()?;
+        if x >= gbuffer.w || y >= gbuffer.h { return None }
+
+        let depth = output.depth().get(x, y);
+        if !depth.is_finite() { return None }
+
+        let index = y as usize * gbuffer.w as usize + x as usize;
+        Some(Pick {
+            object: gbuffer.object_ids[index],
+            triangle_index: gbuffer.triangle_indices[index],
+            depth,
+            position: gbuffer.positions[index],
+        })
+    }
+
+    // The view-space ray through a screen pixel, from the near plane, or
+    // from level with the eye without perspective. Objects' intersect_ray
+    // and intersect_objects pick with it through their hierarchies, with no
+    // g-buffer needed. None if the transform is degenerate.
+    pub fn ray_through(&self, x: Dimension, y: Dimension) -> Option<Ray> {
+        let t = self.transform;
+        let w = t.row(DIM);
+        // The points landing on the pixel lie on two planes, where screen x
+        // and y are its own, which is where the rasterizer samples it.
+        let plane = |r: [Coord; DIM + 1], s: Coord| {
+            let normal = pt![r[0] - s * w[0], r[1] - s * w[1], r[2] - s * w[2]];
+            (normal, r[3] - s * w[3])
+        };
+        let (n1, d1) = plane(t.row(0), x as Coord);
+        let (n2, d2) = plane(t.row(1), y as Coord);
+        let mut direction = n1.cross(n2);
+
+        // Point away from the eye, and start where the near plane or, for
+        // an affine transform, the plane through the eye crosses the line.
+        let w_normal = pt![w[0], w[1], w[2]];
+        let (n3, d3) = if w_normal.dot(direction) != 0. {
+            if w_normal.dot(direction) < 0. { direction = -direction }
+            (w_normal, w[3] - self.near_clip)
+        } else {
+            if direction.z > 0. { direction = -direction }
+            (direction, 0.)
+        };
+        let det = n1.dot(n2.cross(n3));
+        if det == 0. || !det.is_finite() { return None }
+        let origin = (n2.cross(n3) * -d1 + n3.cross(n1) * -d2
+                      + n1.cross(n2) * -d3) * (1. / det);
+        Some(Ray::new(origin, direction.normalized()))
     }
 
     pub fn display(&mut self) -> Result<(), Box<error::Error>> {
-        self.screen.display_texture(&self.texture)
+        self.resolve();
+        let output = self.resolved.as_ref().unwrap_or(&self.texture);
+        self.screen.display_texture(output, &self.tone_mapping)
     }
 
 
     pub fn set_transform(&mut self, t: Transform) {
         self.transform = t;
+        self.frustum.set(None);
     }
 
     pub fn clear_transform(&mut self) {
-        self.transform = Transform::identity();
+        self.set_transform(Transform::identity());
     }
 
     pub fn translate(&mut self, p: Point) {
-        self.transform = Transform::translate(p) * self.transform;
+        let t = Transform::translate(p) * self.transform;
+        self.set_transform(t);
     }
 
-    pub fn rotate_x(&mut self, theta: f64) {
-        self.transform = Transform::rotate_x(theta) * self.transform;
+    pub fn rotate_x(&mut self, theta: Coord) {
+        let t = Transform::rotate_x(theta) * self.transform;
+        self.set_transform(t);
     }
 
-    pub fn rotate_y(&mut self, theta: f64) {
-        self.transform = Transform::rotate_y(theta) * self.transform;
+    pub fn rotate_y(&mut self, theta: Coord) {
+        let t = Transform::rotate_y(theta) * self.transform;
+        self.set_transform(t);
     }
 
-    pub fn rotate_z(&mut self, theta: f64) {
-        self.transform = Transform::rotate_z(theta) * self.transform;
+    pub fn rotate_z(&mut self, theta: Coord) {
+        let t = Transform::rotate_z(theta) * self.transform;
+        self.set_transform(t);
     }
 
-    pub fn scale(&mut self, x: f64, y: f64, z: f64) {
-        self.transform = Transform::scale(x, y, z) * self.transform;
+    pub fn scale(&mut self, x: Coord, y: Coord, z: Coord) {
+        let t = Transform::scale(x, y, z) * self.transform;
+        self.set_transform(t);
     }
 
     pub fn perspective(&mut self) {
-        self.transform = Transform::perspective() * self.transform;
+        let t = Transform::perspective() * self.transform;
+        self.set_transform(t);
     }
 
 
-    pub fn set_color(&mut self, color: Pixel) { self.color = color; }
-    pub fn set_light_pos(&mut self, pos: Point) { self.light = pos; }
+    // Pixels are taken as sRGB and decoded; Colors are already linear.
+    pub fn set_color<C: Into<Color>>(&mut self, color: C) {
+        self.color = color.into();
+    }
+    pub fn set_alpha(&mut self, alpha: f32) { self.alpha = alpha; }
+    pub fn set_rgba(&mut self, color: Rgba) {
+        self.color = color.color();
+        self.alpha = color.a;
+    }
+
+    // Blending applies to everything drawn after it's set. Translucent
+    // surfaces are usually drawn after the opaque ones, back to front, with
+    // depth writes off.
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+        self.texture.set_blend_mode(blend_mode);
+        if let Some(ref mut samples) = self.multisample {
+            samples.set_blend_mode(blend_mode);
+        }
+    }
+
+    pub fn set_depth_write(&mut self, enabled: bool) {
+        self.depth_write = enabled;
+        self.texture.set_depth_write(enabled);
+        if let Some(ref mut samples) = self.multisample {
+            samples.set_depth_write(enabled);
+        }
+    }
+    // Replaces all lights with a white one.
+    pub fn set_light_pos(&mut self, pos: Point) {
+        self.lights = vec![(pos, Color::new(1., 1., 1.))];
+    }
+    pub fn add_light(&mut self, pos: Point, color: Color) {
+        self.lights.push((pos, color));
+    }
+    pub fn clear_lights(&mut self) { self.lights.clear() }
     pub fn set_lighting_mode(&mut self, lighting_mode: LightingMode) {
         self.lighting_mode = lighting_mode;
     }
+    pub fn set_fog_mode(&mut self, fog_mode: FogMode) {
+        self.fog_mode = fog_mode;
+    }
+    pub fn set_fog_color<C: Into<Color>>(&mut self, color: C) {
+        self.fog_color = color.into();
+    }
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) {
+        self.tone_mapping = tone_mapping;
+    }
+    pub fn tone_mapping(&self) -> &ToneMapping { &self.tone_mapping }
+    pub fn set_object_id(&mut self, id: ObjectId) { self.object_id = id; }
+    pub fn set_frustum_culling(&mut self, enabled: bool) {
+        self.frustum_culling = enabled;
+    }
+    pub fn set_near_clip(&mut self, near: Coord) {
+        self.near_clip = near;
+        self.frustum.set(None);
+    }
+}
+
+
+// Orders view-space triangles from farthest to nearest by the depth of their
+// centroids, the order to draw translucent triangles in so that each blends
+// over whatever is behind it. Returns indices into `triangles`.
+pub fn back_to_front(triangles: &[Triangle]) -> Vec<usize> {
+    let depth = |t: &Triangle| -(t.p1.z + t.p2.z + t.p3.z);
+    let mut order: Vec<usize> = (0 .. triangles.len()).collect();
+    order.sort_by(|&a, &b| {
+        depth(&triangles[b]).partial_cmp(&depth(&triangles[a]))
+            .unwrap_or(Equal)
+    });
+    order
 }
@@ -0,0 +1,214 @@
+// A scene graph: a tree of nodes, each placed relative to its parent and
+// carrying a mesh, a light, a camera or nothing. World transforms are cached
+// and only recomputed for nodes that have moved, or whose ancestors have.
+
+use std::cell::Cell;
+
+use animation::Pose;
+use object::Object;
+use pixel;
+use pixel::Color;
+use pixel::Pixel;
+use types::*;
+
+
+pub type NodeId = usize;
+
+pub enum NodeContent {
+    // A group, or a point other nodes hang from.
+    Empty,
+    // The object's own transforms apply beneath the node's.
+    Mesh(Box<Object>),
+    // A point light at the node's origin, shining in the given color. It
+    // can go past white for a brighter light.
+    Light(Color),
+    // A camera at the node's origin, looking down its -z axis.
+    Camera,
+}
+
+// How a mesh is drawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Material {
+    pub color: Pixel,
+}
+
+impl Default for Material {
+    fn default() -> Material { Material { color: pixel::WHITE } }
+}
+
+pub struct Node {
+    pub name: String,
+    pub content: NodeContent,
+    pub material: Material,
+
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    local: Pose,
+
+    // The world transform, valid unless dirty.
+    world: Cell<Transform>,
+    dirty: Cell<bool>,
+}
+
+impl Node {
+    pub fn parent(&self) -> Option<NodeId> { self.parent }
+    pub fn children(&self) -> &[NodeId] { &self.children }
+    pub fn local(&self) -> &Pose { &self.local }
+}
+
+
+pub struct Scene {
+    nodes: Vec<Node>,
+    camera: Option<NodeId>,
+    // What to clear the screen to before rendering the scene.
+    pub background: Pixel,
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        Scene { nodes: Vec::new(), camera: None, background: pixel::BLACK }
+    }
+
+    pub fn add(
+        &mut self,
+        name: &str,
+        content: NodeContent,
+        parent: Option<NodeId>,
+        local: Pose,
+    ) -> NodeId {
+        let id = self.nodes.len();
+        if let Some(p) = parent { self.nodes[p].children.push(id) }
+        self.nodes.push(Node {
+            name: name.to_string(),
+            content,
+            material: Material::default(),
+            parent,
+            children: Vec::new(),
+            local,
+            world: Cell::new(Transform::identity()),
+            dirty: Cell::new(true),
+        });
+        id
+    }
+
+    pub fn len(&self) -> usize { self.nodes.len() }
+    pub fn is_empty(&self) -> bool { self.nodes.is_empty() }
+
+    pub fn nodes(&self) -> &[Node] { &self.nodes }
+    pub fn node(&self, id: NodeId) -> &Node { &self.nodes[id] }
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id]
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.nodes.iter().position(|n| n.name == name)
+    }
+
+    pub fn object(&self, id: NodeId) -> Option<&Object> {
+        match self.nodes[id].content {
+            NodeContent::Mesh(ref object) => Some(object),
+            _ => None,
+        }
+    }
+
+    pub fn object_mut(&mut self, id: NodeId) -> Option<&mut Object> {
+        match self.nodes[id].content {
+            NodeContent::Mesh(ref mut object) => Some(object),
+            _ => None,
+        }
+    }
+
+    // Moves a node under another, or to the root if `parent` is None. Its
+    // local transform is kept, so it moves with its new parent.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) {
+        let mut ancestor = parent;
+        while let Some(a) = ancestor {
+            assert!(a != id, "node {} can't be its own ancestor", id);
+            ancestor = self.nodes[a].parent;
+        }
+
+        if let Some(old) = self.nodes[id].parent {
+            self.nodes[old].children.retain(|&c| c != id);
+        }
+        if let Some(p) = parent { self.nodes[p].children.push(id) }
+        self.nodes[id].parent = parent;
+        self.mark_dirty(id);
+    }
+
+    pub fn set_local(&mut self, id: NodeId, local: Pose) {
+        self.nodes[id].local = local;
+        self.mark_dirty(id);
+    }
+
+    pub fn translate(&mut self, id: NodeId, off: Point) {
+        let mut local = self.nodes[id].local;
+        local.translation = local.translation + off;
+        self.set_local(id, local);
+    }
+
+    pub fn rotate(&mut self, id: NodeId, rotation: Quaternion) {
+        let mut local = self.nodes[id].local;
+        local.rotation = (rotation * local.rotation).normalized();
+        self.set_local(id, local);
+    }
+
+    fn mark_dirty(&self, id: NodeId) {
+        let node = &self.nodes[id];
+        // A dirty node's descendants are all dirty already.
+        if node.dirty.get() { return }
+        node.dirty.set(true);
+        for &child in &node.children { self.mark_dirty(child) }
+    }
+
+    // The node's transform from its own space to the world's.
+    pub fn world_transform(&self, id: NodeId) -> Transform {
+        let node = &self.nodes[id];
+        if node.dirty.get() {
+            let local = node.local.to_transform();
+            node.world.set(match node.parent {
+                Some(p) => self.world_transform(p) * local,
+                None => local,
+            });
+            node.dirty.set(false);
+        }
+        node.world.get()
+    }
+
+    pub fn world_position(&self, id: NodeId) -> Point {
+        pt![0., 0., 0.] * self.world_transform(id)
+    }
+
+    // Picks the camera to view the scene through. Without one, the first
+    // camera node is used.
+    pub fn set_camera(&mut self, id: NodeId) {
+        match self.nodes[id].content {
+            NodeContent::Camera => self.camera = Some(id),
+            _ => panic!("node {} is not a camera", id),
+        }
+    }
+
+    pub fn camera(&self) -> Option<NodeId> {
+        self.camera.or_else(|| {
+            self.nodes.iter()
+                .position(|n| matches!(n.content, NodeContent::Camera))
+        })
+    }
+
+    pub fn lights<'a>(&'a self) -> impl Iterator<Item = NodeId> + 'a {
+        self.nodes.iter().enumerate()
+            .filter(|&(_, n)| matches!(n.content, NodeContent::Light(_)))
+            .map(|(i, _)| i)
+    }
+
+    // Takes world space to the camera's view space, or is the identity if
+    // there's no camera.
+    pub fn view_transform(&self) -> Transform {
+        self.camera()
+            .and_then(|c| self.world_transform(c).inverse())
+            .unwrap_or_else(Transform::identity)
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Scene { Scene::new() }
+}
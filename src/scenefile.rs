@@ -0,0 +1,409 @@
+// A plain-text scene format, for describing scenes outside of code. Each
+// node is a section with a header naming its kind, followed by `key value`
+// lines:
+//
+//     background 0 0 32
+//
+//     [camera]
+//     name eye
+//     translation 0 0 10
+//
+//     [light]
+//     name sun
+//     translation 10 0 10
+//     color 255 240 200
+//     intensity 2
+//
+//     [object]
+//     name cube
+//     mesh res/cube.obj
+//     translation 0 0 -20
+//     euler 0.785 0 0
+//     scale 3 3 3
+//     color 255 128 0
+//
+// Every node takes `name`, `parent` (the name of an earlier node),
+// `translation x y z`, `rotation w x y z` as a quaternion or `euler x y z`
+// in radians, and `scale x y z`. Objects also take `mesh`, a path relative
+// to the scene file, and `color r g b`. Lights take `color r g b`, white if
+// not given, and `intensity`, which scales it and is 1 if not given. A
+// `[group]` has nothing in it, and is there for other nodes to hang from.
+// Blank lines and anything after a `#` are ignored. Names and paths with
+// spaces or `#` in them go in double quotes, with `\"` and `\\` for a quote
+// or backslash inside.
+
+use std::error;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+use animation::Pose;
+use object::Object;
+use pixel;
+use pixel::Color;
+use pixel::Pixel;
+use scene::Material;
+use scene::NodeContent;
+use scene::Scene;
+use types::*;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NodeKind {
+    Group,
+    Camera,
+    Light { color: Pixel, intensity: Coord },
+    Object { mesh: String, material: Material },
+}
+
+#[derive(Clone, Debug)]
+pub struct NodeDesc {
+    pub kind: NodeKind,
+    pub name: String,
+    pub parent: Option<String>,
+    pub pose: Pose,
+    // The line of its header, for nodes read from a file.
+    pub line: Option<usize>,
+}
+
+// A scene as written in a file: what to load and where to put it, before
+// any meshes are read.
+#[derive(Clone, Debug)]
+pub struct SceneFile {
+    pub background: Pixel,
+    pub nodes: Vec<NodeDesc>,
+}
+
+impl SceneFile {
+    pub fn new() -> SceneFile {
+        SceneFile { background: pixel::BLACK, nodes: Vec::new() }
+    }
+
+    pub fn load(filename: &str) -> Result<SceneFile, Box<error::Error>> {
+        let text = fs::read_to_string(filename)
+            .map_err(|e| format!("{}: {}", filename, e))?;
+        SceneFile::parse(&text)
+            .map_err(|e| From::from(format!("{}:{}", filename, e)))
+    }
+
+    // Parses a scene. Errors start with the line number they were found on.
+    pub fn parse(text: &str) -> Result<SceneFile, Box<error::Error>> {
+        let mut file = SceneFile::new();
+        let mut header_lines = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let number = i + 1;
+            let fail = |message: String| -> Box<error::Error> {
+                From::from(format!("{}: {}", number, message))
+            };
+
+            let words = split_words(line).map_err(fail)?;
+            if words.is_empty() { continue }
+
+            if words[0].starts_with('[') {
+                let header = &words[0];
+                if words.len() > 1 || !header.ends_with(']') {
+                    return Err(fail(format!("unclosed header {:?}",
+                                            line.trim())));
+                }
+                let kind = match &header[1 .. header.len() - 1] {
+                    "group"  => NodeKind::Group,
+                    "camera" => NodeKind::Camera,
+                    "light"  => NodeKind::Light {
+                        color: pixel::WHITE,
+                        intensity: 1.,
+                    },
+                    "object" => NodeKind::Object {
+                        mesh: String::new(),
+                        material: Material::default(),
+                    },
+                    other => return Err(fail(format!(
+                        "unknown section [{}]; expected [group], [camera], \
+                         [light] or [object]",
+                        other
+                    ))),
+                };
+                file.nodes.push(NodeDesc {
+                    kind,
+                    name: String::new(),
+                    parent: None,
+                    pose: Pose::identity(),
+                    line: Some(number),
+                });
+                header_lines.push(number);
+                continue;
+            }
+
+            let key = words[0].as_str();
+            let args = &words[1 ..];
+            let node = match file.nodes.last_mut() {
+                Some(node) => node,
+                None => match key {
+                    "background" => {
+                        file.background = parse_color(args).map_err(fail)?;
+                        continue;
+                    },
+                    _ => return Err(fail(format!(
+                        "{:?} must be in a section like [object]",
+                        key
+                    ))),
+                },
+            };
+
+            match (key, &mut node.kind) {
+                ("name", _) => {
+                    node.name = parse_string(args).map_err(fail)?;
+                },
+                ("parent", _) => {
+                    node.parent = Some(parse_string(args).map_err(fail)?);
+                },
+                ("translation", _) => {
+                    node.pose.translation =
+                        parse_point(args).map_err(fail)?;
+                },
+                ("rotation", _) => {
+                    let q = parse_numbers(args, 4).map_err(fail)?;
+                    node.pose.rotation =
+                        Quaternion { w: q[0], x: q[1], y: q[2], z: q[3] };
+                },
+                ("euler", _) => {
+                    let e = parse_point(args).map_err(fail)?;
+                    node.pose.rotation = Quaternion::from_euler(e.x, e.y, e.z);
+                },
+                ("scale", _) => {
+                    node.pose.scale = parse_point(args).map_err(fail)?;
+                },
+                ("mesh", &mut NodeKind::Object { ref mut mesh, .. }) => {
+                    *mesh = parse_string(args).map_err(fail)?;
+                },
+                ("color", &mut NodeKind::Object { ref mut material, .. }) => {
+                    material.color = parse_color(args).map_err(fail)?;
+                },
+                ("color", &mut NodeKind::Light { ref mut color, .. }) => {
+                    *color = parse_color(args).map_err(fail)?;
+                },
+                ("intensity", &mut NodeKind::Light { ref mut intensity, .. })
+                => {
+                    *intensity = parse_numbers(args, 1).map_err(fail)?[0];
+                },
+                _ => return Err(fail(format!(
+                    "unknown key {:?} for this kind of node",
+                    key
+                ))),
+            }
+        }
+
+        // Check that names and parents make a tree.
+        for (i, node) in file.nodes.iter().enumerate() {
+            let fail = |message: String| -> Box<error::Error> {
+                From::from(format!("{}: {}", header_lines[i], message))
+            };
+            if node.name.is_empty() {
+                return Err(fail("node has no name".to_string()));
+            }
+            let earlier = &file.nodes[.. i];
+            if earlier.iter().any(|n| n.name == node.name) {
+                return Err(fail(format!("duplicate name {:?}", node.name)));
+            }
+            if let Some(ref parent) = node.parent {
+                if !earlier.iter().any(|n| &n.name == parent) {
+                    return Err(fail(format!(
+                        "parent {:?} is not a node above this one",
+                        parent
+                    )));
+                }
+            }
+            if let NodeKind::Object { ref mesh, .. } = node.kind {
+                if mesh.is_empty() {
+                    return Err(fail(format!("object {:?} has no mesh",
+                                            node.name)));
+                }
+            }
+        }
+
+        Ok(file)
+    }
+
+    // Writes the scene in a form that parses back to the same thing.
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let (r, g, b) = self.background;
+        writeln!(out, "background {} {} {}", r, g, b)?;
+
+        for node in &self.nodes {
+            writeln!(out)?;
+            let header = match node.kind {
+                NodeKind::Group => "group",
+                NodeKind::Camera => "camera",
+                NodeKind::Light { .. } => "light",
+                NodeKind::Object { .. } => "object",
+            };
+            writeln!(out, "[{}]", header)?;
+            writeln!(out, "name {}", quote(&node.name))?;
+            if let Some(ref parent) = node.parent {
+                writeln!(out, "parent {}", quote(parent))?;
+            }
+            if let NodeKind::Object { ref mesh, .. } = node.kind {
+                writeln!(out, "mesh {}", quote(mesh))?;
+            }
+
+            let Pose { translation: t, rotation: q, scale: s } = node.pose;
+            writeln!(out, "translation {} {} {}", t.x, t.y, t.z)?;
+            writeln!(out, "rotation {} {} {} {}", q.w, q.x, q.y, q.z)?;
+            writeln!(out, "scale {} {} {}", s.x, s.y, s.z)?;
+
+            if let NodeKind::Object { ref material, .. } = node.kind {
+                let (r, g, b) = material.color;
+                writeln!(out, "color {} {} {}", r, g, b)?;
+            }
+            if let NodeKind::Light { color: (r, g, b), intensity } = node.kind {
+                writeln!(out, "color {} {} {}", r, g, b)?;
+                writeln!(out, "intensity {}", intensity)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, filename: &str) -> io::Result<()> {
+        let mut out = io::BufWriter::new(fs::File::create(filename)?);
+        self.write(&mut out)
+    }
+
+    // Builds the scene, loading meshes from paths relative to `dir`. Errors
+    // for nodes read from a file start with the line of their header.
+    pub fn build(&self, dir: &Path) -> Result<Scene, Box<error::Error>> {
+        let mut scene = Scene::new();
+        scene.background = self.background;
+
+        for node in &self.nodes {
+            let content = match node.kind {
+                NodeKind::Group => NodeContent::Empty,
+                NodeKind::Camera => NodeContent::Camera,
+                NodeKind::Light { color, intensity } => NodeContent::Light(
+                    Color::from(color) * intensity as f32
+                ),
+                NodeKind::Object { ref mesh, .. } => {
+                    let path = dir.join(mesh);
+                    let path = path.to_string_lossy();
+                    let object = Object::from_file(&path).map_err(|e| {
+                        let message = format!(
+                            "{}: can't load mesh for {:?}: {}",
+                            path, node.name, e
+                        );
+                        match node.line {
+                            Some(line) => format!("{}: {}", line, message),
+                            None => message,
+                        }
+                    })?;
+                    NodeContent::Mesh(Box::new(object))
+                },
+            };
+            let parent = node.parent.as_ref().and_then(|p| scene.find(p));
+            let id = scene.add(&node.name, content, parent, node.pose);
+            if let NodeKind::Object { material, .. } = node.kind {
+                scene.node_mut(id).material = material;
+            }
+        }
+
+        Ok(scene)
+    }
+}
+
+impl Default for SceneFile {
+    fn default() -> SceneFile { SceneFile::new() }
+}
+
+// Loads a scene file and the meshes it refers to.
+pub fn load_scene(filename: &str) -> Result<Scene, Box<error::Error>> {
+    let dir = Path::new(filename).parent().unwrap_or_else(|| Path::new(""));
+    SceneFile::load(filename)?.build(dir)
+        .map_err(|e| From::from(format!("{}:{}", filename, e)))
+}
+
+
+// Splits a line into words at whitespace, up to any `#`. A word in double
+// quotes may hold anything, with `\"` and `\\` standing for `"` and `\`.
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let mut word = String::new();
+        match chars.next() {
+            None | Some('#') => return Ok(words),
+            Some('"') => loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c @ '"') | Some(c @ '\\') => word.push(c),
+                        _ => return Err(format!(
+                            "bad escape in {:?}; use \\\" or \\\\",
+                            line.trim()
+                        )),
+                    },
+                    Some(c) => word.push(c),
+                    None => return Err(format!(
+                        "unclosed quote in {:?}",
+                        line.trim()
+                    )),
+                }
+            },
+            Some(c) => {
+                word.push(c);
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '#' { break }
+                    word.push(c);
+                    chars.next();
+                }
+            },
+        }
+        words.push(word);
+    }
+}
+
+// Quotes a string for split_words, if it needs it.
+fn quote(s: &str) -> String {
+    let plain = |c: char| !c.is_whitespace() && c != '#' && c != '"';
+    if !s.is_empty() && s.chars().all(plain) { return s.to_string() }
+    let escaped = s.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{}\"", escaped)
+}
+
+fn parse_string(args: &[String]) -> Result<String, String> {
+    match args.len() {
+        0 => Err("expected a value".to_string()),
+        1 => Ok(args[0].clone()),
+        _ => Err(format!(
+            "expected one value, found {}; put names with spaces in quotes",
+            args.len()
+        )),
+    }
+}
+
+fn parse_numbers(args: &[String], count: usize) -> Result<Vec<Coord>, String> {
+    if args.len() != count {
+        return Err(format!("expected {} numbers, found {}", count, args.len()));
+    }
+    args.iter()
+        .map(|a| a.parse::<Coord>()
+             .map_err(|_| format!("{:?} is not a number", a)))
+        .collect()
+}
+
+fn parse_point(args: &[String]) -> Result<Point, String> {
+    let v = parse_numbers(args, 3)?;
+    Ok(pt![v[0], v[1], v[2]])
+}
+
+fn parse_color(args: &[String]) -> Result<Pixel, String> {
+    if args.len() != 3 {
+        return Err(format!("expected 3 color components, found {}",
+                           args.len()));
+    }
+    let channel = |a: &str| a.parse::<u8>()
+        .map_err(|_| format!("{:?} is not a color component from 0 to 255",
+                             a));
+    Ok((channel(&args[0])?, channel(&args[1])?, channel(&args[2])?))
+}
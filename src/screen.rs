@@ -3,13 +3,21 @@ use sdl2::pixels::PixelFormatEnum;
 
 use std::error;
 
+use format::PixelFormat;
+use pixel::Pixel;
 use texture::Texture;
+use tonemap::ToneMapping;
 use types::*;
 
 
 pub trait Screen {
-    fn display_texture(&mut self, texture: &Texture)
-        -> Result<(), Box<error::Error>>;
+    // Shows a texture in any pixel format. Each pixel is converted for
+    // display by its format, which tone maps the float formats.
+    fn display_texture<P: PixelFormat>(
+        &mut self,
+        texture: &Texture<P>,
+        tone_mapping: &ToneMapping,
+    ) -> Result<(), Box<error::Error>>;
 
     fn width (&self) -> Dimension;
     fn height(&self) -> Dimension;
@@ -33,10 +41,15 @@ impl TextScreen {
 }
 
 impl Screen for TextScreen {
-    fn display_texture(&mut self, texture: &Texture)
-        -> Result<(), Box<error::Error>>
+    fn display_texture<P: PixelFormat>(
+        &mut self,
+        texture: &Texture<P>,
+        tone_mapping: &ToneMapping,
+    ) -> Result<(), Box<error::Error>>
     {
-        println!("{}", texture);
+        let mut mapped: Texture<Pixel> = Texture::new(texture.w, texture.h);
+        mapped.pixels = texture.to_pixels(tone_mapping);
+        println!("{}", mapped);
         Ok(())
     }
 
@@ -84,13 +97,16 @@ impl<'a> GraphicalScreen<'a> {
 }
 
 impl<'a> Screen for GraphicalScreen<'a> {
-    fn display_texture(&mut self, texture: &Texture)
-        -> Result<(), Box<error::Error>>
+    fn display_texture<P: PixelFormat>(
+        &mut self,
+        texture: &Texture<P>,
+        tone_mapping: &ToneMapping,
+    ) -> Result<(), Box<error::Error>>
     {
         assert!(texture.w == self.w && texture.h == self.h);
         self.texture.with_lock(None, |buf: &mut [u8], _: usize| {
             for i in 0 .. texture.pixels.len() {
-                let (r,g,b) = texture.pixels[i];
+                let (r,g,b) = texture.pixels[i].to_display(tone_mapping);
                 buf[3 * i]     = r;
                 buf[3 * i + 1] = g;
                 buf[3 * i + 2] = b;
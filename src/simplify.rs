@@ -0,0 +1,360 @@
+// Mesh decimation by quadric error edge collapse, after Garland and
+// Heckbert, "Surface Simplification Using Quadric Error Metrics" (1997).
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::collections::HashSet;
+
+use mesh::IndexedMesh;
+use mesh::edge_key;
+use types::*;
+
+
+// How much more moving a boundary costs than moving across the surface.
+const BOUNDARY_WEIGHT: Coord = 1000.;
+
+// When to stop collapsing edges.
+#[derive(Clone, Copy, Debug)]
+pub struct Target {
+    // Stop once this many triangles remain. There are never fewer, but may
+    // be more if no edge left can be collapsed without going under.
+    pub triangles: usize,
+    // Never make a collapse that costs more than this, measured as summed
+    // squared distance from the original surface.
+    pub max_error: Coord,
+}
+
+impl Target {
+    pub fn triangles(n: usize) -> Target {
+        Target { triangles: n, max_error: Coord::INFINITY }
+    }
+
+    pub fn error(max_error: Coord) -> Target {
+        Target { triangles: 0, max_error }
+    }
+}
+
+// A symmetric 4x4 matrix, stored as its upper triangle.
+#[derive(Clone, Copy)]
+struct Quadric([Coord; 10]);
+
+impl Quadric {
+    fn zero() -> Quadric { Quadric([0.; 10]) }
+
+    // The squared distance to the plane ax + by + cz + d = 0, scaled by
+    // weight, where (a, b, c) is a unit normal.
+    fn from_plane(n: Point, d: Coord, weight: Coord) -> Quadric {
+        let (a, b, c) = (n.x, n.y, n.z);
+        let q = [
+            a * a, a * b, a * c, a * d,
+                   b * b, b * c, b * d,
+                          c * c, c * d,
+                                 d * d,
+        ];
+        Quadric([
+            q[0] * weight, q[1] * weight, q[2] * weight, q[3] * weight,
+            q[4] * weight, q[5] * weight, q[6] * weight, q[7] * weight,
+            q[8] * weight, q[9] * weight,
+        ])
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) { *a += *b }
+    }
+
+    fn error(&self, p: Point) -> Coord {
+        let q = &self.0;
+        let (x, y, z) = (p.x, p.y, p.z);
+        q[0] * x * x + 2. * q[1] * x * y + 2. * q[2] * x * z + 2. * q[3] * x
+            + q[4] * y * y + 2. * q[5] * y * z + 2. * q[6] * y
+            + q[7] * z * z + 2. * q[8] * z
+            + q[9]
+    }
+
+    // Solves for the point of least error, if it is well defined.
+    fn minimizer(&self) -> Option<Point> {
+        let q = &self.0;
+        let (a, b, c) = (q[0], q[1], q[2]);
+        let (d, e, f) = (q[4], q[5], q[7]);
+        let det = a * (d * f - e * e) - b * (b * f - e * c)
+            + c * (b * e - d * c);
+        if det.abs() < 1e-12 { return None }
+
+        // Cramer's rule on the symmetric system with right-hand side -r.
+        let r = [-q[3], -q[6], -q[8]];
+        let det_x = r[0] * (d * f - e * e) - b * (r[1] * f - e * r[2])
+            + c * (r[1] * e - d * r[2]);
+        let det_y = a * (r[1] * f - e * r[2]) - r[0] * (b * f - e * c)
+            + c * (b * r[2] - r[1] * c);
+        let det_z = a * (d * r[2] - r[1] * e) - b * (b * r[2] - r[1] * c)
+            + r[0] * (b * e - d * c);
+        Some(pt![det_x / det, det_y / det, det_z / det])
+    }
+}
+
+// A possible collapse of edge (a, b), valid while neither vertex has changed
+// since it was costed.
+struct Candidate {
+    cost: Coord,
+    a: usize,
+    b: usize,
+    stamps: (u32, u32),
+    position: Point,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Candidate) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Candidate) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    // Reversed, so that the heap pops the cheapest collapse first. Ties,
+    // common on flat regions, go to the lowest edge, so that the result
+    // doesn't depend on the order candidates were pushed.
+    fn cmp(&self, other: &Candidate) -> Ordering {
+        other.cost.partial_cmp(&self.cost)
+            .unwrap_or(Ordering::Equal)
+            .then((other.a, other.b).cmp(&(self.a, self.b)))
+            .then(other.stamps.cmp(&self.stamps))
+    }
+}
+
+struct Decimator {
+    positions: Vec<Point>,
+    faces: Vec<[usize; 3]>,
+    face_alive: Vec<bool>,
+    vertex_faces: Vec<Vec<usize>>,
+    quadrics: Vec<Quadric>,
+    on_boundary: Vec<bool>,
+    stamps: Vec<u32>,
+    heap: BinaryHeap<Candidate>,
+}
+
+impl Decimator {
+    fn new(mesh: IndexedMesh) -> Decimator {
+        let n = mesh.positions.len();
+        let mut vertex_faces = vec![Vec::new(); n];
+        let mut quadrics = vec![Quadric::zero(); n];
+
+        for (i, f) in mesh.faces.iter().enumerate() {
+            let [p0, p1, p2] = [
+                mesh.positions[f[0]],
+                mesh.positions[f[1]],
+                mesh.positions[f[2]],
+            ];
+            let cross = (p1 - p0).cross(p2 - p0);
+            let area = cross.magnitude() * 0.5;
+            if area > 0. {
+                let normal = cross * (1. / (2. * area));
+                let q = Quadric::from_plane(normal, -normal.dot(p0), area);
+                for &v in f { quadrics[v].add(&q) }
+            }
+            for &v in f { vertex_faces[v].push(i) }
+        }
+
+        // Pin boundary edges with planes perpendicular to their faces.
+        let edge_counts = mesh.edge_face_counts();
+        let mut on_boundary = vec![false; n];
+        for f in &mesh.faces {
+            let [p0, p1, p2] = [
+                mesh.positions[f[0]],
+                mesh.positions[f[1]],
+                mesh.positions[f[2]],
+            ];
+            let face_normal = (p1 - p0).cross(p2 - p0);
+            if face_normal.magnitude() == 0. { continue }
+            for k in 0 .. 3 {
+                let (a, b) = (f[k], f[(k + 1) % 3]);
+                if edge_counts[&edge_key(a, b)] != 1 { continue }
+                on_boundary[a] = true;
+                on_boundary[b] = true;
+                let edge = mesh.positions[b] - mesh.positions[a];
+                let normal = edge.cross(face_normal).normalized();
+                let q = Quadric::from_plane(
+                    normal,
+                    -normal.dot(mesh.positions[a]),
+                    BOUNDARY_WEIGHT * edge.dot(edge),
+                );
+                quadrics[a].add(&q);
+                quadrics[b].add(&q);
+            }
+        }
+
+        let num_faces = mesh.faces.len();
+        let mut decimator = Decimator {
+            positions: mesh.positions,
+            faces: mesh.faces,
+            face_alive: vec![true; num_faces],
+            vertex_faces,
+            quadrics,
+            on_boundary,
+            stamps: vec![0; n],
+            heap: BinaryHeap::new(),
+        };
+        let mut edges: Vec<(usize, usize)> =
+            edge_counts.keys().cloned().collect();
+        edges.sort();
+        for (a, b) in edges {
+            decimator.push_candidate(a, b);
+        }
+        decimator
+    }
+
+    fn push_candidate(&mut self, a: usize, b: usize) {
+        let mut q = self.quadrics[a];
+        q.add(&self.quadrics[b]);
+
+        // Boundary vertices stay where they are, so a boundary only ever
+        // loses vertices.
+        let (pa, pb) = (self.positions[a], self.positions[b]);
+        let options = match (self.on_boundary[a], self.on_boundary[b]) {
+            (true,  true)  => vec![pa, pb],
+            (true,  false) => vec![pa],
+            (false, true)  => vec![pb],
+            (false, false) => {
+                let mut options = vec![pa, pb, (pa + pb) * 0.5];
+                if let Some(p) = q.minimizer() { options.push(p) }
+                options
+            },
+        };
+        let (position, cost) = options.into_iter()
+            .map(|p| (p, q.error(p)))
+            .min_by(|x, y| x.1.partial_cmp(&y.1).unwrap_or(Ordering::Equal))
+            .unwrap();
+
+        self.heap.push(Candidate {
+            cost: cost.max(0.),
+            a,
+            b,
+            stamps: (self.stamps[a], self.stamps[b]),
+            position,
+        });
+    }
+
+    fn alive_faces(&self, v: usize) -> Vec<usize> {
+        self.vertex_faces[v].iter()
+            .cloned()
+            .filter(|&f| self.face_alive[f])
+            .collect()
+    }
+
+    fn neighbors(&self, v: usize) -> HashSet<usize> {
+        self.alive_faces(v).iter()
+            .flat_map(|&f| self.faces[f].to_vec())
+            .filter(|&u| u != v)
+            .collect()
+    }
+
+    // Checks that collapsing (a, b) to `position` keeps the mesh manifold and
+    // flips no faces.
+    fn can_collapse(&self, a: usize, b: usize, position: Point) -> bool {
+        // Link condition: a and b may only share the vertices opposite the
+        // edge in the faces on it.
+        let shared_faces = self.alive_faces(a).iter()
+            .filter(|&&f| self.faces[f].contains(&b))
+            .count();
+        let shared_neighbors = self.neighbors(a)
+            .intersection(&self.neighbors(b))
+            .count();
+        if shared_neighbors != shared_faces { return false }
+
+        for &v in &[a, b] {
+            for f in self.alive_faces(v) {
+                let face = self.faces[f];
+                if face.contains(&a) && face.contains(&b) { continue }
+
+                let old = face.iter().map(|&u| self.positions[u]);
+                let new = face.iter().map(|&u| {
+                    if u == a || u == b { position } else { self.positions[u] }
+                });
+                let (old, new): (Vec<Point>, Vec<Point>) =
+                    (old.collect(), new.collect());
+                let old_n = (old[1] - old[0]).cross(old[2] - old[0]);
+                let new_n = (new[1] - new[0]).cross(new[2] - new[0]);
+                if old_n.dot(new_n) <= 0. { return false }
+            }
+        }
+        true
+    }
+
+    fn collapse(&mut self, a: usize, b: usize, position: Point) -> usize {
+        let mut removed = 0;
+        for f in self.alive_faces(b) {
+            if self.faces[f].contains(&a) {
+                self.face_alive[f] = false;
+                removed += 1;
+            } else {
+                for u in self.faces[f].iter_mut() {
+                    if *u == b { *u = a }
+                }
+                self.vertex_faces[a].push(f);
+            }
+        }
+        self.vertex_faces[b].clear();
+        let live = self.alive_faces(a);
+        self.vertex_faces[a] = live;
+
+        self.positions[a] = position;
+        self.on_boundary[a] = self.on_boundary[a] || self.on_boundary[b];
+        let qb = self.quadrics[b];
+        self.quadrics[a].add(&qb);
+        self.stamps[a] += 1;
+        self.stamps[b] += 1;
+
+        for u in self.neighbors(a) {
+            self.push_candidate(a, u);
+        }
+        removed
+    }
+
+    fn run(&mut self, target: Target) {
+        let mut num_faces = self.faces.len();
+        while num_faces > target.triangles {
+            let c = match self.heap.pop() {
+                Some(c) => c,
+                None => break,
+            };
+            if c.stamps != (self.stamps[c.a], self.stamps[c.b]) { continue }
+            if c.cost > target.max_error { break }
+            // An edge inside the mesh takes two faces with it; near the
+            // target, wait for a boundary edge that takes one instead.
+            let on_edge = self.alive_faces(c.a).iter()
+                .filter(|&&f| self.faces[f].contains(&c.b))
+                .count();
+            if num_faces - on_edge < target.triangles { continue }
+            if !self.can_collapse(c.a, c.b, c.position) { continue }
+            num_faces -= self.collapse(c.a, c.b, c.position);
+        }
+    }
+
+    fn into_mesh(self) -> IndexedMesh {
+        let faces = self.faces.iter()
+            .zip(self.face_alive.iter())
+            .filter(|&(_, &alive)| alive)
+            .map(|(f, _)| *f)
+            .collect();
+        IndexedMesh { positions: self.positions, faces }
+    }
+}
+
+// Reduces a mesh by collapsing its cheapest edges until the target is met,
+// or no more edges can be collapsed. Boundaries are kept in place as far as
+// possible. Unused vertices are left in the result.
+pub fn simplify(mesh: IndexedMesh, target: Target) -> IndexedMesh {
+    let mut decimator = Decimator::new(mesh);
+    decimator.run(target);
+    decimator.into_mesh()
+}
+
+pub fn simplify_triangles(tris: &[Triangle], target: Target) -> Vec<Triangle> {
+    simplify(IndexedMesh::from_triangles(tris), target).to_triangles()
+}
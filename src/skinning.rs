@@ -0,0 +1,264 @@
+// Skeletal animation: a hierarchy of joints posed by animation clips, and
+// meshes whose vertices follow up to four joints each by linear blend
+// skinning. The layout follows glTF skins: each joint has an inverse bind
+// matrix, and each vertex four joint indices and weights.
+
+use animation::Clip;
+use animation::PlayMode;
+use animation::Pose;
+use format::PixelFormat;
+use object::Object;
+use renderer::Renderer;
+use screen::Screen;
+use types::*;
+
+
+pub const MAX_INFLUENCES: usize = 4;
+
+#[derive(Clone, Debug)]
+pub struct Joint {
+    pub name: String,
+    // Joints may come in any order, so long as there are no cycles.
+    pub parent: Option<usize>,
+    // Relative to the parent joint, or to the skeleton for a root.
+    pub pose: Pose,
+    // Takes points from the mesh's space to this joint's space in the bind
+    // pose, i.e. the inverse of the joint's world transform in that pose.
+    pub inverse_bind: Transform,
+}
+
+impl Joint {
+    pub fn new(name: &str, parent: Option<usize>, pose: Pose) -> Joint {
+        Joint {
+            name: name.to_string(),
+            parent,
+            pose,
+            inverse_bind: Transform::identity(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+}
+
+impl Skeleton {
+    pub fn new(joints: Vec<Joint>) -> Skeleton {
+        for (i, joint) in joints.iter().enumerate() {
+            if let Some(p) = joint.parent {
+                assert!(p < joints.len(), "joint {} has no parent {}", i, p);
+            }
+        }
+        Skeleton { joints }
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|j| j.name == name)
+    }
+
+    // Each joint's transform from its own space to the skeleton's.
+    pub fn world_transforms(&self) -> Vec<Transform> {
+        let mut world = vec![None; self.joints.len()];
+        for i in 0 .. self.joints.len() {
+            self.world_transform(i, &mut world, 0);
+        }
+        world.into_iter().map(Option::unwrap).collect()
+    }
+
+    fn world_transform(
+        &self,
+        i: usize,
+        world: &mut [Option<Transform>],
+        depth: usize,
+    ) -> Transform {
+        if let Some(t) = world[i] { return t }
+        assert!(depth < self.joints.len(), "joint hierarchy has a cycle");
+        let local = self.joints[i].pose.to_transform();
+        let t = match self.joints[i].parent {
+            Some(p) => self.world_transform(p, world, depth + 1) * local,
+            None => local,
+        };
+        world[i] = Some(t);
+        t
+    }
+
+    // Takes the current pose as the bind pose, setting each joint's inverse
+    // bind matrix from it.
+    pub fn set_bind_pose(&mut self) {
+        let world_transforms = self.world_transforms();
+        for (joint, world) in self.joints.iter_mut().zip(world_transforms) {
+            joint.inverse_bind = world.inverse()
+                .expect("joint transform is singular");
+        }
+    }
+
+    // The matrices that take bind-pose vertices to their posed positions,
+    // one per joint.
+    pub fn skinning_transforms(&self) -> Vec<Transform> {
+        self.world_transforms().into_iter()
+            .zip(self.joints.iter())
+            .map(|(world, joint)| world * joint.inverse_bind)
+            .collect()
+    }
+}
+
+
+// The joints a vertex follows and how strongly, with weights adding up to
+// one. Unused slots have a weight of zero.
+#[derive(Clone, Copy, Debug)]
+pub struct Influences {
+    pub joints: [usize; MAX_INFLUENCES],
+    pub weights: [Coord; MAX_INFLUENCES],
+}
+
+impl Influences {
+    // Normalizes the weights. If they are all zero, the vertex follows the
+    // first joint.
+    pub fn new(
+        joints: [usize; MAX_INFLUENCES],
+        weights: [Coord; MAX_INFLUENCES],
+    ) -> Influences {
+        let total: Coord = weights.iter().sum();
+        let mut normalized = [0.; MAX_INFLUENCES];
+        if total > 0. {
+            for (n, w) in normalized.iter_mut().zip(weights.iter()) {
+                *n = w / total;
+            }
+        } else {
+            normalized[0] = 1.;
+        }
+        Influences { joints, weights: normalized }
+    }
+
+    pub fn single(joint: usize) -> Influences {
+        Influences::new([joint, 0, 0, 0], [1., 0., 0., 0.])
+    }
+
+    // Blends the joints' transforms of a point by weight.
+    fn skin_point(&self, transforms: &[Transform], p: Point) -> Point {
+        self.joints.iter().zip(self.weights.iter())
+            .filter(|&(_, &w)| w > 0.)
+            .fold(pt![0., 0., 0.], |sum, (&j, &w)| sum + p * transforms[j] * w)
+    }
+
+    fn skin_normal(&self, normal_matrices: &[Transform], n: Point) -> Point {
+        self.joints.iter().zip(self.weights.iter())
+            .filter(|&(_, &w)| w > 0.)
+            .fold(pt![0., 0., 0.], |sum, (&j, &w)| {
+                sum + normal_matrices[j].transform_direction(n) * w
+            })
+            .normalized()
+    }
+}
+
+
+// An Object whose triangles are deformed by a skeleton. The object's own
+// transforms then place the skinned mesh in the world.
+pub struct SkinnedMesh {
+    // The mesh in its bind pose.
+    bind_triangles: Vec<Triangle>,
+    bind_normals: Option<Vec<[Point; 3]>>,
+    // One entry per vertex of each triangle, as with normals.
+    influences: Vec<[Influences; 3]>,
+
+    object: Object,
+}
+
+impl SkinnedMesh {
+    // Takes the object's triangles and normals as the bind pose.
+    pub fn new(object: Object, influences: Vec<[Influences; 3]>)
+        -> SkinnedMesh
+    {
+        assert!(influences.len() == object.triangles().len());
+        SkinnedMesh {
+            bind_triangles: object.triangles().to_vec(),
+            bind_normals: object.normals().map(|n| n.to_vec()),
+            influences,
+            object,
+        }
+    }
+
+    pub fn object(&self) -> &Object { &self.object }
+    pub fn object_mut(&mut self) -> &mut Object { &mut self.object }
+
+    // Deforms the mesh to follow the skeleton's current pose.
+    pub fn update(&mut self, skeleton: &Skeleton) {
+        let transforms = skeleton.skinning_transforms();
+        for inf in self.influences.iter().flat_map(|i| i.iter()) {
+            for (&j, &w) in inf.joints.iter().zip(inf.weights.iter()) {
+                assert!(w == 0. || j < transforms.len(),
+                        "vertex follows missing joint {}", j);
+            }
+        }
+
+        let triangles = self.bind_triangles.iter()
+            .zip(self.influences.iter())
+            .map(|(t, inf)| trigon![
+                inf[0].skin_point(&transforms, t.p1),
+                inf[1].skin_point(&transforms, t.p2),
+                inf[2].skin_point(&transforms, t.p3)
+            ])
+            .collect();
+
+        let normals = self.bind_normals.as_ref().map(|normals| {
+            let normal_matrices: Vec<Transform> = transforms.iter()
+                .map(|t| t.normal_matrix().unwrap_or(*t))
+                .collect();
+            normals.iter()
+                .zip(self.influences.iter())
+                .map(|(n, inf)| [
+                    inf[0].skin_normal(&normal_matrices, n[0]),
+                    inf[1].skin_normal(&normal_matrices, n[1]),
+                    inf[2].skin_normal(&normal_matrices, n[2]),
+                ])
+                .collect()
+        });
+
+        self.object.set_triangles(triangles, normals);
+    }
+
+    pub fn render<S: Screen, P: PixelFormat>(
+        &self,
+        renderer: &mut Renderer<S, P>,
+    ) {
+        self.object.render(renderer);
+    }
+}
+
+
+// Clips that drive the joints of a skeleton, like a glTF animation with
+// channels targeting several nodes. The clips play in step, over the
+// length of the longest.
+#[derive(Clone, Debug)]
+pub struct SkeletalAnimation {
+    // Pairs of joint index and the clip that poses it.
+    pub channels: Vec<(usize, Clip)>,
+    pub mode: PlayMode,
+}
+
+impl SkeletalAnimation {
+    pub fn new(mode: PlayMode) -> SkeletalAnimation {
+        SkeletalAnimation { channels: Vec::new(), mode }
+    }
+
+    pub fn add_channel(&mut self, joint: usize, clip: Clip) {
+        self.channels.push((joint, clip));
+    }
+
+    pub fn duration(&self) -> Coord {
+        self.channels.iter()
+            .map(|(_, clip)| clip.duration())
+            .fold(0., Coord::max)
+    }
+
+    // Poses the skeleton's joints as the animation has them at a time.
+    // Parts of a joint's pose that no clip animates are left alone.
+    pub fn apply(&self, time: Coord, skeleton: &mut Skeleton) {
+        let time = self.mode.local_time(time, self.duration());
+        for &(joint, ref clip) in &self.channels {
+            let joint = &mut skeleton.joints[joint];
+            joint.pose = clip.sample_local(time, &joint.pose);
+        }
+    }
+}
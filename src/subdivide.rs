@@ -0,0 +1,282 @@
+// Subdivision surfaces: Loop for triangle meshes and Catmull–Clark for
+// polygon meshes. Boundaries and marked crease edges stay sharp. Vertices
+// where more than two of them meet, or that touch only one face, stay put as
+// corners.
+
+use std::collections::HashSet;
+
+use mesh::HalfEdgeMesh;
+use mesh::PolyMesh;
+use mesh::edge_key;
+use types::*;
+
+
+// Edges to keep sharp, as pairs of vertex indices in either order.
+pub type Creases = HashSet<(usize, usize)>;
+
+// Returns the edges of a mesh whose faces meet at more than `angle`
+// radians, e.g. the edges of a cube for any angle under a right angle.
+pub fn creases_by_angle(mesh: &PolyMesh, angle: Coord) -> Creases {
+    let topology = HalfEdgeMesh::new(mesh.positions.len(), &mesh.faces);
+    let normals: Vec<Point> = mesh.faces.iter()
+        .map(|f| face_normal(&mesh.positions, f))
+        .collect();
+    let threshold = angle.cos();
+
+    topology.edges()
+        .filter(|&h| match topology.half_edges[h].twin {
+            Some(t) => {
+                let (f, g) = (topology.half_edges[h].face,
+                              topology.half_edges[t].face);
+                normals[f].dot(normals[g]) < threshold
+            },
+            None => false,
+        })
+        .map(|h| edge_key(topology.origin(h), topology.dest(h)))
+        .collect()
+}
+
+fn face_normal(positions: &[Point], face: &[usize]) -> Point {
+    // Newell's method, which handles non-planar polygons.
+    let mut n = pt![0., 0., 0.];
+    for (k, &i) in face.iter().enumerate() {
+        let (a, b) = (positions[i], positions[face[(k + 1) % face.len()]]);
+        n = n + pt![
+            (a.y - b.y) * (a.z + b.z),
+            (a.z - b.z) * (a.x + b.x),
+            (a.x - b.x) * (a.y + b.y)
+        ];
+    }
+    n.normalized()
+}
+
+fn average<I: Iterator<Item = Point>>(points: I) -> Point {
+    let (sum, n) = points
+        .fold((pt![0., 0., 0.], 0), |(s, n), p| (s + p, n + 1));
+    sum * (1. / n as Coord)
+}
+
+// Shared state for one level of either scheme.
+struct Level<'a> {
+    mesh: &'a PolyMesh,
+    topology: HalfEdgeMesh,
+    creases: &'a Creases,
+}
+
+impl<'a> Level<'a> {
+    fn new(mesh: &'a PolyMesh, creases: &'a Creases) -> Level<'a> {
+        Level {
+            mesh,
+            topology: HalfEdgeMesh::new(mesh.positions.len(), &mesh.faces),
+            creases,
+        }
+    }
+
+    fn is_sharp(&self, h: usize) -> bool {
+        self.topology.is_boundary(h) || self.creases.contains(
+            &edge_key(self.topology.origin(h), self.topology.dest(h))
+        )
+    }
+
+    // Positions a vertex on a crease or corner, or returns None if it is
+    // smooth and up to the scheme.
+    fn sharp_vertex(&self, v: usize) -> Option<Point> {
+        let p = self.mesh.positions[v];
+        // A vertex of a lone face, like the corner of an open quad.
+        if self.topology.outgoing(v).len() == 1 { return Some(p) }
+
+        let sharp: Vec<usize> = self.topology.vertex_edges(v).into_iter()
+            .filter(|&h| self.is_sharp(h))
+            .map(|h| self.topology.other(h, v))
+            .collect();
+        match sharp.len() {
+            0 | 1 => None,
+            2 => Some(
+                p * 0.75
+                    + (self.mesh.positions[sharp[0]]
+                        + self.mesh.positions[sharp[1]]) * 0.125
+            ),
+            _ => Some(p),
+        }
+    }
+
+    // Maps each edge's canonical half-edge to the index of the vertex that
+    // will split it.
+    fn number_edges(&self, first: usize) -> Vec<usize> {
+        let mut edge_vertex = vec![0; self.topology.half_edges.len()];
+        for (i, h) in self.topology.edges().enumerate() {
+            edge_vertex[h] = first + i;
+        }
+        edge_vertex
+    }
+
+    fn edge_vertex(&self, edge_vertex: &[usize], h: usize) -> usize {
+        edge_vertex[self.topology.canonical(h)]
+    }
+
+    // Carries creases over to the two halves of each split edge.
+    fn split_creases(&self, edge_vertex: &[usize]) -> Creases {
+        self.creases.iter()
+            .filter_map(|&(a, b)| {
+                self.topology.outgoing(a).iter()
+                    .chain(self.topology.outgoing(b).iter())
+                    .find(|&&h| edge_key(self.topology.origin(h),
+                                         self.topology.dest(h)) == (a, b))
+                    .map(|&h| self.edge_vertex(edge_vertex, h))
+                    .map(|e| vec![edge_key(a, e), edge_key(e, b)])
+            })
+            .flatten()
+            .collect()
+    }
+}
+
+// Loop subdivision of a triangle mesh. Faces that aren't triangles are fanned
+// into triangles first.
+pub fn loop_subdivide(mesh: &PolyMesh, levels: usize, creases: &Creases)
+    -> PolyMesh
+{
+    let mut mesh = if mesh.faces.iter().all(|f| f.len() == 3) {
+        mesh.clone()
+    } else {
+        PolyMesh::from(mesh.triangulate())
+    };
+    let mut creases = creases.clone();
+    for _ in 0 .. levels {
+        let (next, next_creases) = loop_level(&mesh, &creases);
+        mesh = next;
+        creases = next_creases;
+    }
+    mesh
+}
+
+fn loop_level(mesh: &PolyMesh, creases: &Creases) -> (PolyMesh, Creases) {
+    let level = Level::new(mesh, creases);
+    let topo = &level.topology;
+    let pos = &mesh.positions;
+
+    // Vertex points.
+    let mut positions: Vec<Point> = (0 .. pos.len())
+        .map(|v| level.sharp_vertex(v).unwrap_or_else(|| {
+            let neighbors: Vec<usize> = topo.vertex_edges(v).into_iter()
+                .map(|h| topo.other(h, v))
+                .collect();
+            let n = neighbors.len();
+            if n == 0 { return pos[v] }
+            let beta = if n == 3 { 3. / 16. } else { 3. / (8. * n as Coord) };
+            let sum = neighbors.iter()
+                .fold(pt![0., 0., 0.], |s, &u| s + pos[u]);
+            pos[v] * (1. - n as Coord * beta) + sum * beta
+        }))
+        .collect();
+
+    // Edge points.
+    let edge_vertex = level.number_edges(positions.len());
+    for h in topo.edges() {
+        let (a, b) = (pos[topo.origin(h)], pos[topo.dest(h)]);
+        positions.push(match topo.half_edges[h].twin {
+            Some(t) if !level.is_sharp(h) => {
+                let c = pos[topo.origin(topo.prev(h))];
+                let d = pos[topo.origin(topo.prev(t))];
+                (a + b) * 0.375 + (c + d) * 0.125
+            },
+            _ => (a + b) * 0.5,
+        });
+    }
+
+    // Each triangle becomes four.
+    let mut faces = Vec::with_capacity(4 * mesh.faces.len());
+    for &first in &topo.face_edges {
+        let h0 = first;
+        let h1 = topo.half_edges[h0].next;
+        let h2 = topo.half_edges[h1].next;
+        let (v0, v1, v2) = (topo.origin(h0), topo.origin(h1), topo.origin(h2));
+        let e0 = level.edge_vertex(&edge_vertex, h0);
+        let e1 = level.edge_vertex(&edge_vertex, h1);
+        let e2 = level.edge_vertex(&edge_vertex, h2);
+        faces.push(vec![v0, e0, e2]);
+        faces.push(vec![v1, e1, e0]);
+        faces.push(vec![v2, e2, e1]);
+        faces.push(vec![e0, e1, e2]);
+    }
+
+    let next_creases = level.split_creases(&edge_vertex);
+    (PolyMesh { positions, faces }, next_creases)
+}
+
+// Catmull–Clark subdivision. Any polygons are accepted, and every face of
+// the result is a quad.
+pub fn catmull_clark(mesh: &PolyMesh, levels: usize, creases: &Creases)
+    -> PolyMesh
+{
+    let mut mesh = mesh.clone();
+    let mut creases = creases.clone();
+    for _ in 0 .. levels {
+        let (next, next_creases) = catmull_clark_level(&mesh, &creases);
+        mesh = next;
+        creases = next_creases;
+    }
+    mesh
+}
+
+fn catmull_clark_level(mesh: &PolyMesh, creases: &Creases)
+    -> (PolyMesh, Creases)
+{
+    let level = Level::new(mesh, creases);
+    let topo = &level.topology;
+    let pos = &mesh.positions;
+
+    let face_points: Vec<Point> = mesh.faces.iter()
+        .map(|f| average(f.iter().map(|&v| pos[v])))
+        .collect();
+
+    // Vertex points.
+    let mut positions: Vec<Point> = (0 .. pos.len())
+        .map(|v| level.sharp_vertex(v).unwrap_or_else(|| {
+            let edges = topo.vertex_edges(v);
+            let n = edges.len();
+            if n == 0 { return pos[v] }
+            let f = average(topo.outgoing(v).iter()
+                .map(|&h| face_points[topo.half_edges[h].face]));
+            let r = average(edges.iter()
+                .map(|&h| (pos[topo.origin(h)] + pos[topo.dest(h)]) * 0.5));
+            let n = n as Coord;
+            (f + r * 2. + pos[v] * (n - 3.)) * (1. / n)
+        }))
+        .collect();
+
+    // Edge points.
+    let edge_vertex = level.number_edges(positions.len());
+    for h in topo.edges() {
+        let (a, b) = (pos[topo.origin(h)], pos[topo.dest(h)]);
+        positions.push(match topo.half_edges[h].twin {
+            Some(t) if !level.is_sharp(h) => {
+                let f = face_points[topo.half_edges[h].face];
+                let g = face_points[topo.half_edges[t].face];
+                (a + b + f + g) * 0.25
+            },
+            _ => (a + b) * 0.5,
+        });
+    }
+
+    // Each n-gon becomes n quads around its face point.
+    let first_face_point = positions.len();
+    positions.extend(face_points);
+    let mut faces = Vec::new();
+    for (f, &first) in topo.face_edges.iter().enumerate() {
+        let mut h = first;
+        loop {
+            let prev = topo.prev(h);
+            faces.push(vec![
+                topo.origin(h),
+                level.edge_vertex(&edge_vertex, h),
+                first_face_point + f,
+                level.edge_vertex(&edge_vertex, prev),
+            ]);
+            h = topo.half_edges[h].next;
+            if h == first { break }
+        }
+    }
+
+    let next_creases = level.split_creases(&edge_vertex);
+    (PolyMesh { positions, faces }, next_creases)
+}
@@ -0,0 +1,191 @@
+// Terrain meshes built from height fields, either images or functions.
+// Large terrains can be split into tiles, which are culled separately.
+
+use std::error;
+use std::fs;
+
+use netpbm;
+use object::Object;
+use types::*;
+
+
+// A grid of heights in [0, 1], such as a grayscale image.
+pub struct Heightmap {
+    pub w: Dimension,
+    pub h: Dimension,
+    heights: Vec<Coord>,
+}
+
+// The shape of a terrain mesh in the world.
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainSpec {
+    // Extent along x and z. The terrain is centered on the origin.
+    pub width: Coord,
+    pub depth: Coord,
+    // World height of the highest point of a heightmap.
+    pub height: Coord,
+    // Number of grid cells along x and z.
+    pub cells_x: usize,
+    pub cells_z: usize,
+}
+
+impl Heightmap {
+    pub fn new(w: Dimension, h: Dimension, heights: Vec<Coord>) -> Heightmap {
+        assert!(heights.len() == w as usize * h as usize);
+        Heightmap { w, h, heights }
+    }
+
+    // Loads a PGM or PPM image. Color images are converted to luma.
+    pub fn from_file(filename: &str) -> Result<Heightmap, Box<error::Error>> {
+        let mut f = fs::File::open(filename)?;
+        let image = netpbm::read_pnm(&mut f)?;
+        let heights = image.samples
+            .chunks(image.channels)
+            .map(|s| match s.len() {
+                3 => 0.2126 * s[0] + 0.7152 * s[1] + 0.0722 * s[2],
+                _ => s[0],
+            } as Coord)
+            .collect();
+        Ok(Heightmap::new(image.w, image.h, heights))
+    }
+
+    fn get(&self, x: usize, y: usize) -> Coord {
+        self.heights[y * self.w as usize + x]
+    }
+
+    // Samples with bilinear filtering, where u and v run from 0 to 1 across
+    // the map.
+    pub fn sample(&self, u: Coord, v: Coord) -> Coord {
+        let max_x = (self.w - 1) as Coord;
+        let max_y = (self.h - 1) as Coord;
+        let x = (u * max_x).max(0.).min(max_x);
+        let y = (v * max_y).max(0.).min(max_y);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let x1 = (x0 + 1).min(self.w as usize - 1);
+        let y1 = (y0 + 1).min(self.h as usize - 1);
+        let (tx, ty) = (x - x0 as Coord, y - y0 as Coord);
+
+        let top    = self.get(x0, y0) * (1. - tx) + self.get(x1, y0) * tx;
+        let bottom = self.get(x0, y1) * (1. - tx) + self.get(x1, y1) * tx;
+        top * (1. - ty) + bottom * ty
+    }
+}
+
+impl TerrainSpec {
+    fn grid_x(&self, i: usize) -> Coord {
+        self.width * (i as Coord / self.cells_x as Coord - 0.5)
+    }
+
+    fn grid_z(&self, j: usize) -> Coord {
+        self.depth * (j as Coord / self.cells_z as Coord - 0.5)
+    }
+}
+
+pub fn from_heightmap(map: &Heightmap, spec: &TerrainSpec) -> Object {
+    tiles_from_heightmap(map, spec, 1, 1).pop().unwrap()
+}
+
+// Builds a terrain from a function giving the height at a world x and z.
+pub fn from_fn<F>(f: F, spec: &TerrainSpec) -> Object
+    where F: Fn(Coord, Coord) -> Coord
+{
+    tiles_from_fn(f, spec, 1, 1).pop().unwrap()
+}
+
+// Like from_heightmap, but split into tiles_x by tiles_z objects, in rows
+// along x.
+pub fn tiles_from_heightmap(
+    map: &Heightmap,
+    spec: &TerrainSpec,
+    tiles_x: usize,
+    tiles_z: usize,
+) -> Vec<Object> {
+    build(spec, tiles_x, tiles_z, |i, j| {
+        let u = i as Coord / spec.cells_x as Coord;
+        let v = j as Coord / spec.cells_z as Coord;
+        map.sample(u, v) * spec.height
+    })
+}
+
+// Like from_fn, but split into tiles_x by tiles_z objects, in rows along x.
+// The spec's height is not used.
+pub fn tiles_from_fn<F>(
+    f: F,
+    spec: &TerrainSpec,
+    tiles_x: usize,
+    tiles_z: usize,
+) -> Vec<Object>
+    where F: Fn(Coord, Coord) -> Coord
+{
+    build(spec, tiles_x, tiles_z, |i, j| f(spec.grid_x(i), spec.grid_z(j)))
+}
+
+fn build<H>(
+    spec: &TerrainSpec,
+    tiles_x: usize,
+    tiles_z: usize,
+    height_at: H,
+) -> Vec<Object>
+    where H: Fn(usize, usize) -> Coord
+{
+    let (nx, nz) = (spec.cells_x.max(1), spec.cells_z.max(1));
+    let spec = TerrainSpec { cells_x: nx, cells_z: nz, ..*spec };
+    let (tiles_x, tiles_z) = (tiles_x.max(1).min(nx), tiles_z.max(1).min(nz));
+
+    // Sample the whole grid first, so that normals match across tiles.
+    let heights: Vec<Coord> = (0 .. nz + 1)
+        .flat_map(|j| (0 .. nx + 1).map(move |i| (i, j)))
+        .map(|(i, j)| height_at(i, j))
+        .collect();
+    let height = |i: usize, j: usize| heights[j * (nx + 1) + i];
+    let position = |i: usize, j: usize| {
+        pt![spec.grid_x(i), height(i, j), spec.grid_z(j)]
+    };
+    let normal = |i: usize, j: usize| {
+        // Central differences, one-sided at the edges.
+        let (i0, i1) = (i.saturating_sub(1), (i + 1).min(nx));
+        let (j0, j1) = (j.saturating_sub(1), (j + 1).min(nz));
+        let dx = spec.grid_x(i1) - spec.grid_x(i0);
+        let dz = spec.grid_z(j1) - spec.grid_z(j0);
+        let slope_x = (height(i1, j) - height(i0, j)) / dx;
+        let slope_z = (height(i, j1) - height(i, j0)) / dz;
+        pt![-slope_x, 1., -slope_z].normalized()
+    };
+    let uv = |i: usize, j: usize| {
+        (i as Coord / nx as Coord, j as Coord / nz as Coord)
+    };
+
+    let mut tiles = Vec::with_capacity(tiles_x * tiles_z);
+    for tz in 0 .. tiles_z {
+        for tx in 0 .. tiles_x {
+            let (i_start, i_end) = (tx * nx / tiles_x, (tx + 1) * nx / tiles_x);
+            let (j_start, j_end) = (tz * nz / tiles_z, (tz + 1) * nz / tiles_z);
+
+            let mut tris = Vec::new();
+            let mut normals = Vec::new();
+            let mut uvs = Vec::new();
+            for j in j_start .. j_end {
+                for i in i_start .. i_end {
+                    // Wound to face up the y axis.
+                    let quad = [(i, j), (i, j + 1), (i + 1, j + 1), (i + 1, j)];
+                    for &[a, b, c] in &[[0, 1, 2], [0, 2, 3]] {
+                        let (a, b, c) = (quad[a], quad[b], quad[c]);
+                        tris.push(trigon![
+                            position(a.0, a.1),
+                            position(b.0, b.1),
+                            position(c.0, c.1)
+                        ]);
+                        normals.push([
+                            normal(a.0, a.1),
+                            normal(b.0, b.1),
+                            normal(c.0, c.1),
+                        ]);
+                        uvs.push([uv(a.0, a.1), uv(b.0, b.1), uv(c.0, c.1)]);
+                    }
+                }
+            }
+            tiles.push(Object::with_attributes(tris, normals, uvs));
+        }
+    }
+    tiles
+}
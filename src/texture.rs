@@ -1,105 +1,397 @@
-use std::f64;
 use std::fmt;
 use std::fmt::Display;
+use std::io;
+use std::io::Write;
 
+use abuffer::ABuffer;
+use abuffer::Fragment;
+use depth::DepthView;
+use format::PixelFormat;
+use gbuffer::GBuffer;
+use gbuffer::GBufferRect;
+use gbuffer::Surface;
+use netpbm;
 use pixel;
+use pixel::BlendMode;
+use pixel::Color;
 use pixel::Pixel;
+use pixel::Rgba;
+use tonemap::ToneMapping;
 use types::*;
 use utils::*;
 
 
-pub struct Texture {
+// Pixels in any format, with a z-buffer. By default they are linear,
+// unclamped Colors; see tonemap for getting pixels out.
+pub struct Texture<P: PixelFormat = Color> {
     pub w: Dimension,
     pub h: Dimension,
-    pub pixels: Vec<Pixel>,
+    pub pixels: Vec<P>,
     z_buffer:   Vec<Coord>,
+
+    pub gbuffer: Option<GBuffer>,
+    // When present, blended pixels are collected here rather than drawn.
+    pub abuffer: Option<ABuffer>,
+
+    blend_mode:  BlendMode,
+    // Off for translucent surfaces, so those behind them still get drawn.
+    depth_write: bool,
 }
 
-impl Texture {
-    pub fn new(w: Dimension, h: Dimension) -> Texture {
+impl<P: PixelFormat> Texture<P> {
+    pub fn new(w: Dimension, h: Dimension) -> Texture<P> {
         let num_pixels = w as usize * h as usize;
+        let black = P::from_rgba(Rgba::from(pixel::BLACK));
         Texture {
             w: w,
             h: h,
-            pixels:   vec![pixel::BLACK;  num_pixels],
-            z_buffer: vec![f64::INFINITY; num_pixels],
+            pixels:   vec![black; num_pixels],
+            z_buffer: vec![Coord::INFINITY; num_pixels],
+
+            gbuffer: None,
+            abuffer: None,
+
+            blend_mode:  BlendMode::Replace,
+            depth_write: true,
         }
     }
 
-    pub fn set_pixel(
+    pub fn set_pixel<C: Into<Rgba>>(
         &mut self,
         x: PixCoord,
         y: PixCoord,
         z: Coord,
-        color: Pixel
+        color: C
     ) {
         if x < 0 || y < 0 { return }
-        if self.w < x as Dimension || self.h < y as Dimension { return }
+        if x as Dimension >= self.w || y as Dimension >= self.h { return }
         self.set_pixel_nocheck(x, y, z, color)
     }
 
-    pub fn set_pixel_nocheck(
+    // Depth tests the pixel, then blends it in. The depth and g-buffer are
+    // only written if depth writes are on. With an A-buffer, pixels drawn
+    // with any blend mode but Replace become fragments, blended in when the
+    // A-buffer is resolved.
+    pub fn set_pixel_nocheck<C: Into<Rgba>>(
+        &mut self,
+        x: PixCoord,
+        y: PixCoord,
+        z: Coord,
+        color: C
+    ) {
+        self.rect_mut().set_pixel_nocheck(x, y, z, color);
+    }
+
+    // Writes a pixel and its depth without a depth test, and without
+    // touching the g-buffer, as when resolving samples into a texture.
+    pub fn overwrite_pixel(
         &mut self,
         x: PixCoord,
         y: PixCoord,
         z: Coord,
-        color: Pixel
+        color: P
     ) {
         let index = y as usize * self.w as usize + x as usize;
-        if z >= self.z_buffer[index] { return }
         self.z_buffer[index] = z;
         self.pixels[index]   = color;
     }
 
-    pub fn set_row(
+    pub fn set_row<C: Into<Rgba> + Copy>(
         &mut self,
         x1: PixCoord,
         x2: PixCoord,
         y:  PixCoord,
         z1: Coord,
         z2: Coord,
-        color: Pixel
+        color: C
     ) {
-        if y  < 0 || (           y as Dimension >= self.h) { return }
-        if x2 < 0 || (x1 > 0 && x1 as Dimension >= self.w) { return }
+        self.set_row_with(x1, x2, y, z1, z2, |_| color);
+    }
+
+    // Like set_row, but asks `shade` for the color of each pixel given its
+    // interpolated depth.
+    pub fn set_row_with<C, F>(
+        &mut self,
+        x1: PixCoord,
+        x2: PixCoord,
+        y:  PixCoord,
+        z1: Coord,
+        z2: Coord,
+        shade: F
+    )
+        where C: Into<Rgba>,
+              F: Fn(Coord) -> C
+    {
+        self.rect_mut().set_row_with(x1, x2, y, z1, z2, shade);
+    }
 
-        let start = clamp(x1, 0, (self.w - 1) as PixCoord);
-        let end   = clamp(x2, 0, (self.w - 1) as PixCoord);
+    pub fn rect_mut(&mut self) -> TextureRect<'_, P> { self.rect_at(0, 0) }
 
-        if x2 <= x1 { return }
-        for x in start .. end + 1 {
-            let t = ((x - x1) as f64) / ((x2 - x1) as f64);
-            let z = z1 * (1. - t) + z2 * t;
-            self.set_pixel_nocheck(x, y, z, color);
+    // The texture as the rectangle of a larger one at (x0, y0), e.g. a tile
+    // drawn to apart from the rest.
+    pub fn rect_at(&mut self, x0: PixCoord, y0: PixCoord)
+        -> TextureRect<'_, P>
+    {
+        TextureRect {
+            x0,
+            y0,
+            w: self.w,
+            h: self.h,
+            pixels:   &mut self.pixels,
+            z_buffer: &mut self.z_buffer,
+            gbuffer: self.gbuffer.as_mut().map(|g| g.rect_at(x0, y0)),
+            abuffer: self.abuffer.as_mut(),
+            surface: None,
+            blend_mode:  self.blend_mode,
+            depth_write: self.depth_write,
         }
     }
 
-    pub fn set_all_pixels(&mut self, color: Pixel) {
+    // A copy of the w by h rectangle with its top left corner at (x, y),
+    // with the g-buffer and drawing state but no A-buffer.
+    pub fn copy_rect(
+        &self,
+        x: Dimension,
+        y: Dimension,
+        w: Dimension,
+        h: Dimension,
+    ) -> Texture<P> {
+        let mut rect = Texture::new(w, h);
+        rect.gbuffer = self.gbuffer.as_ref().map(|g| g.copy_rect(x, y, w, h));
+        let (from, size) = ((x as usize, y as usize), (w as usize, h as usize));
+        let (self_w, w) = (self.w as usize, w as usize);
+        copy_rect(&self.pixels, self_w, from,
+                  &mut rect.pixels, w, (0, 0), size);
+        copy_rect(&self.z_buffer, self_w, from,
+                  &mut rect.z_buffer, w, (0, 0), size);
+        rect.blend_mode  = self.blend_mode;
+        rect.depth_write = self.depth_write;
+        rect
+    }
+
+    // Writes back a rectangle from copy_rect, to (x, y).
+    pub fn paste_rect(
+        &mut self,
+        x: Dimension,
+        y: Dimension,
+        rect: &Texture<P>,
+    ) {
+        let (to, size) = ((x as usize, y as usize),
+                          (rect.w as usize, rect.h as usize));
+        let (self_w, w) = (self.w as usize, rect.w as usize);
+        copy_rect(&rect.pixels, w, (0, 0), &mut self.pixels, self_w, to, size);
+        copy_rect(&rect.z_buffer, w, (0, 0),
+                  &mut self.z_buffer, self_w, to, size);
+        if let (Some(gbuffer), Some(from)) =
+            (self.gbuffer.as_mut(), rect.gbuffer.as_ref())
+        {
+            gbuffer.paste_rect(x, y, from);
+        }
+    }
+
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+    }
+
+    pub fn set_depth_write(&mut self, enabled: bool) {
+        self.depth_write = enabled;
+    }
+
+    pub fn enable_gbuffer(&mut self) {
+        if self.gbuffer.is_none() {
+            self.gbuffer = Some(GBuffer::new(self.w, self.h));
+        }
+    }
+
+    pub fn disable_gbuffer(&mut self) { self.gbuffer = None; }
+
+    pub fn enable_abuffer(&mut self, max_fragments: usize) {
+        self.abuffer = Some(ABuffer::new(self.w, self.h, max_fragments));
+    }
+
+    pub fn disable_abuffer(&mut self) { self.abuffer = None; }
+
+    // Blends the A-buffer's fragments into the pixels and empties it.
+    pub fn resolve_abuffer(&mut self) {
+        if let Some(abuffer) = self.abuffer.as_mut() {
+            abuffer.resolve(&mut self.pixels, &self.z_buffer);
+        }
+    }
+
+    pub fn depth(&self) -> DepthView<'_> {
+        DepthView::new(self.w, self.h, &self.z_buffer)
+    }
+
+    pub fn set_all_pixels<C: Into<Rgba>>(&mut self, color: C) {
+        let color = P::from_rgba(color.into());
         for i in 0..self.pixels.len() {
             self.pixels[i] = color;
         }
     }
 
     pub fn clear(&mut self) {
+        self.clear_to(pixel::BLACK);
+    }
+
+    pub fn clear_to<C: Into<Rgba>>(&mut self, color: C) {
+        let color = P::from_rgba(color.into());
         for i in 0 .. self.pixels.len() {
-            self.pixels[i]   = pixel::BLACK;
-            self.z_buffer[i] = f64::INFINITY;
+            self.pixels[i]   = color;
+            self.z_buffer[i] = Coord::INFINITY;
+        }
+        if let Some(gbuffer) = self.gbuffer.as_mut() { gbuffer.clear() }
+        if let Some(abuffer) = self.abuffer.as_mut() { abuffer.clear() }
+    }
+
+    pub fn to_pixels(&self, tone_mapping: &ToneMapping) -> Vec<Pixel> {
+        self.pixels.iter().map(|p| p.to_display(tone_mapping)).collect()
+    }
+
+    // A copy in another format, with the same depths but no g-buffer or
+    // A-buffer.
+    pub fn convert<Q: PixelFormat>(&self) -> Texture<Q> {
+        let mut texture = Texture::new(self.w, self.h);
+        texture.pixels = self.pixels.iter().map(|p| p.convert()).collect();
+        texture.z_buffer = self.z_buffer.clone();
+        texture
+    }
+
+    // Writes the tone mapped image as a binary PPM.
+    pub fn write_ppm<W: Write>(&self, out: &mut W, tone_mapping: &ToneMapping)
+        -> io::Result<()>
+    {
+        netpbm::write_ppm(out, self.w, self.h, &self.to_pixels(tone_mapping))
+    }
+
+    // Writes linear colors, unclamped, as a three-channel PFM.
+    pub fn write_pfm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let values: Vec<f32> = self.pixels.iter()
+            .map(|p| p.to_rgba())
+            .flat_map(|c| vec![c.r, c.g, c.b])
+            .collect();
+        netpbm::write_pfm(out, self.w, self.h, 3, &values)
+    }
+
+    pub fn save_ppm(&self, filename: &str, tone_mapping: &ToneMapping)
+        -> io::Result<()>
+    {
+        self.write_ppm(&mut netpbm::create(filename)?, tone_mapping)
+    }
+
+    pub fn save_pfm(&self, filename: &str) -> io::Result<()> {
+        self.write_pfm(&mut netpbm::create(filename)?)
+    }
+}
+
+
+// Mutable access to a texture as a rectangle of a larger one, with its top
+// left corner at (x0, y0), and with its own blending state and surface.
+// Coordinates are those of the larger texture; pixels outside the
+// rectangle are skipped.
+pub struct TextureRect<'a, P: PixelFormat> {
+    pub x0: PixCoord,
+    pub y0: PixCoord,
+    pub w: Dimension,
+    pub h: Dimension,
+    pixels:   &'a mut [P],
+    z_buffer: &'a mut [Coord],
+
+    gbuffer: Option<GBufferRect<'a>>,
+    abuffer: Option<&'a mut ABuffer>,
+
+    // What pixel writes record into the g-buffer. Writes with no surface,
+    // like lines and points, empty the pixels they write depth to.
+    pub surface:     Option<Surface>,
+    // As for Texture.
+    pub blend_mode:  BlendMode,
+    pub depth_write: bool,
+}
+
+impl<'a, P: PixelFormat> TextureRect<'a, P> {
+    // Depth tests the pixel, then blends it in. The depth and g-buffer are
+    // only written if depth writes are on. With an A-buffer, pixels drawn
+    // with any blend mode but Replace become fragments, blended in when the
+    // A-buffer is resolved.
+    pub fn set_pixel_nocheck<C: Into<Rgba>>(
+        &mut self,
+        x: PixCoord,
+        y: PixCoord,
+        z: Coord,
+        color: C
+    ) {
+        let index = (y - self.y0) as usize * self.w as usize
+            + (x - self.x0) as usize;
+        if z >= self.z_buffer[index] { return }
+        if let Some(abuffer) = self.abuffer.as_mut() {
+            if self.blend_mode != BlendMode::Replace {
+                abuffer.insert(x, y, Fragment {
+                    color: color.into(),
+                    depth: z,
+                    blend_mode: self.blend_mode,
+                });
+                return;
+            }
+        }
+        self.pixels[index] = match self.blend_mode {
+            BlendMode::Replace => P::from_rgba(color.into()),
+            mode => P::from_rgba(
+                mode.blend_rgba(color.into(), self.pixels[index].to_rgba())
+            ),
+        };
+        if !self.depth_write { return }
+        self.z_buffer[index] = z;
+
+        if let Some(gbuffer) = self.gbuffer.as_mut() {
+            match self.surface {
+                Some(ref surface) => gbuffer.set(x, y, surface),
+                None => gbuffer.unset(x, y),
+            }
+        }
+    }
+
+    // Asks `shade` for the color of each pixel given its interpolated depth.
+    pub fn set_row_with<C, F>(
+        &mut self,
+        x1: PixCoord,
+        x2: PixCoord,
+        y:  PixCoord,
+        z1: Coord,
+        z2: Coord,
+        shade: F
+    )
+        where C: Into<Rgba>,
+              F: Fn(Coord) -> C
+    {
+        if y < self.y0 || (y - self.y0) as Dimension >= self.h { return }
+        let last = self.x0 + self.w as PixCoord - 1;
+        if x2 < self.x0 || x1 > last { return }
+
+        let start = clamp(x1, self.x0, last);
+        let end   = clamp(x2, self.x0, last);
+
+        if x2 <= x1 { return }
+        for x in start .. end + 1 {
+            let t = (x as Coord - x1 as Coord) / (x2 as Coord - x1 as Coord);
+            let z = z1 * (1. - t) + z2 * t;
+            self.set_pixel_nocheck(x, y, z, shade(z));
         }
     }
 }
 
-impl Display for Texture {
+impl<P: PixelFormat> Display for Texture<P> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         // Draw top bar.
         try!(write!(f, "{:-^1$}\n", "", self.w as usize * 2 + 3));
 
         // Draw rows.
+        let tone_mapping = ToneMapping::new();
         self.pixels
             .chunks(self.w as usize)
             .map(|row| {
                 try!(write!(f, "| "));
                 for p in row {
-                    try!(write!(f, "{} ", pixel::as_char(*p)));
+                    let c = pixel::as_char(p.to_display(&tone_mapping));
+                    try!(write!(f, "{} ", c));
                 }
                 try!(write!(f, "|\n"));
                 Ok(())
@@ -0,0 +1,250 @@
+// Tile-based rasterization across threads. Triangles are transformed, lit
+// and set up as they're submitted, then kept until the frame is flushed.
+// Flushing bins each triangle into the square tiles its bounding box
+// touches and draws the tiles in parallel. Each tile takes its triangles in
+// the order they were submitted, so every pixel sees the same writes in the
+// same order as when drawing straight to the texture, and the image comes
+// out the same.
+//
+// A worker draws a tile into its own copy of that part of the texture,
+// which is written back once every tile is done.
+
+use std::cmp::Ordering::Equal;
+use std::mem;
+use std::sync::Mutex;
+use std::thread;
+
+use format::PixelFormat;
+use gbuffer::Surface;
+use pixel::BlendMode;
+use pixel::Color;
+use pixel::Rgba;
+use renderer::FogMode;
+use texture::Texture;
+use texture::TextureRect;
+use types::*;
+
+
+// The width and height of a tile, in pixels.
+pub const TILE_SIZE: Dimension = 32;
+
+// How a triangle is colored: its lit color, fogged per pixel by depth.
+// Fog is blended after lighting, which is already baked into the color.
+#[derive(Clone, Copy, Debug)]
+pub struct Shading {
+    pub color: Color,
+    pub alpha: f32,
+    pub fog_mode: FogMode,
+    pub fog_color: Color,
+}
+
+impl Shading {
+    pub fn at(&self, z: Coord) -> Rgba {
+        match self.fog_mode {
+            FogMode::NoFog => Rgba::from_color(self.color, self.alpha),
+            fog_mode => {
+                let visibility = fog_mode.visibility(z) as f32;
+                let fogged = self.fog_color.lerp(self.color, visibility);
+                Rgba::from_color(fogged, self.alpha)
+            },
+        }
+    }
+}
+
+// A triangle ready to be scan converted: screen x and y with view depth in
+// z, and everything needed to draw it.
+#[derive(Clone, Copy)]
+pub struct SetupTriangle {
+    pub pts: [Point; 3],
+    pub shading: Shading,
+    pub surface: Option<Surface>,
+    pub blend_mode: BlendMode,
+    pub depth_write: bool,
+}
+
+impl SetupTriangle {
+    pub fn draw<P: PixelFormat>(&self, rect: &mut TextureRect<P>) {
+        rect.surface     = self.surface;
+        rect.blend_mode  = self.blend_mode;
+        rect.depth_write = self.depth_write;
+        let shading = self.shading;
+        scan_triangle(self.pts, |x1, x2, y, z1, z2| {
+            rect.set_row_with(x1, x2, y, z1, z2, |z| shading.at(z));
+        });
+    }
+
+    // The first and last columns and rows scan_triangle may visit. Its row
+    // ends lie between the corners, and truncate the same way.
+    fn pixel_bounds(&self) -> (PixCoord, PixCoord, PixCoord, PixCoord) {
+        let xs = self.pts.iter().map(|p| p.x as PixCoord);
+        let ys = self.pts.iter().map(|p| p.y as PixCoord);
+        (xs.clone().min().unwrap(), xs.max().unwrap(),
+         ys.clone().min().unwrap(), ys.max().unwrap())
+    }
+}
+
+// Splits a triangle into flat-bottomed and flat-topped halves and calls
+// `row` with the ends and depths of each row of them.
+pub fn scan_triangle<F>(mut pts: [Point; 3], mut row: F)
+    where F: FnMut(PixCoord, PixCoord, PixCoord, Coord, Coord)
+{
+    // Sort points by y coord.
+    pts.sort_by(
+        |p1, p2|
+        p1.y.partial_cmp(&p2.y)
+            .unwrap_or(Equal)
+    );
+    let (top, middle, bot) = (pts[0], pts[1], pts[2]);
+
+    const EPSILON: Coord = 1.;
+    if middle.y - top.y < EPSILON {
+        scan_top_flat_triangle(Triangle::from_arr(pts), &mut row);
+    } else if bot.y - middle.y < EPSILON {
+        scan_bottom_flat_triangle(Triangle::from_arr(pts), &mut row);
+    } else {
+        let dy_mid: Coord = middle.y - top.y;
+        let dy_bot: Coord = bot.y - top.y;
+        let dx_bot: Coord = bot.x - top.x;
+        let dz_bot: Coord = bot.z - top.z;
+
+        let v4 = pt![
+            top.x + dx_bot * dy_mid / dy_bot,
+            middle.y,
+            top.z + dz_bot * dy_mid / dy_bot
+        ];
+        scan_bottom_flat_triangle(trigon![top, middle, v4], &mut row);
+        scan_top_flat_triangle(trigon![middle, v4, bot], &mut row);
+    }
+}
+
+fn scan_bottom_flat_triangle<F>(t: Triangle, row: &mut F)
+    where F: FnMut(PixCoord, PixCoord, PixCoord, Coord, Coord)
+{
+    let (top, mut left, mut right) = t.to_tuple();
+    if left.x > right.x { mem::swap(&mut left, &mut right) }
+
+    for y in top.y as PixCoord .. left.y as PixCoord {
+        let t = (y - top.y as PixCoord) as Coord / (left.y - top.y);
+
+        let z_left  = top.z + t * (left.z  - top.z);
+        let z_right = top.z + t * (right.z - top.z);
+
+        row(
+            (top.x + (left.x  - top.x) * t) as PixCoord,
+            (top.x + (right.x - top.x) * t) as PixCoord,
+            y,
+            z_left,
+            z_right
+        );
+    }
+}
+
+fn scan_top_flat_triangle<F>(t: Triangle, row: &mut F)
+    where F: FnMut(PixCoord, PixCoord, PixCoord, Coord, Coord)
+{
+    let (mut left, mut right, bot) = t.to_tuple();
+    if left.x > right.x { mem::swap(&mut left, &mut right) }
+
+    for y in left.y as PixCoord .. bot.y as PixCoord + 1 {
+        let t       = (y - left.y as PixCoord) as Coord / (bot.y - left.y);
+        let z_left  = left.z  + t * (bot.z - left.z);
+        let z_right = right.z + t * (bot.z - right.z);
+
+        row(
+            (left.x  + (bot.x - left.x)  * t) as PixCoord,
+            (right.x + (bot.x - right.x) * t) as PixCoord,
+            y,
+            z_left,
+            z_right
+        );
+    }
+}
+
+
+// Triangles submitted since the last flush, and how many threads to draw
+// them with.
+pub struct TileRasterizer {
+    threads: usize,
+    triangles: Vec<SetupTriangle>,
+}
+
+impl TileRasterizer {
+    pub fn new(threads: usize) -> TileRasterizer {
+        assert!(threads > 0, "can't rasterize with no threads");
+        TileRasterizer {
+            threads,
+            triangles: Vec::new(),
+        }
+    }
+
+    pub fn threads(&self) -> usize { self.threads }
+
+    pub fn is_empty(&self) -> bool { self.triangles.is_empty() }
+
+    pub fn push(&mut self, triangle: SetupTriangle) {
+        self.triangles.push(triangle);
+    }
+
+    // Drops the triangles submitted since the last flush.
+    pub fn clear(&mut self) { self.triangles.clear() }
+
+    // Draws the triangles submitted since the last flush to `texture`, which
+    // mustn't have an A-buffer.
+    pub fn flush<P: PixelFormat>(&mut self, texture: &mut Texture<P>) {
+        if self.triangles.is_empty() { return }
+        assert!(texture.abuffer.is_none(), "can't split an A-buffer");
+
+        let size = TILE_SIZE as PixCoord;
+        let columns = (texture.w as PixCoord + size - 1) / size;
+        let rows = (texture.h as PixCoord + size - 1) / size;
+        let mut bins: Vec<Vec<usize>> =
+            vec![Vec::new(); (columns * rows) as usize];
+        for (i, triangle) in self.triangles.iter().enumerate() {
+            let (left, right, top, bot) = triangle.pixel_bounds();
+            if right < 0 || bot < 0 { continue }
+            let tiles = |first: PixCoord, last: PixCoord, count: PixCoord| {
+                first.max(0) / size .. (last / size).min(count - 1) + 1
+            };
+            for row in tiles(top, bot, rows) {
+                for column in tiles(left, right, columns) {
+                    bins[(row * columns + column) as usize].push(i);
+                }
+            }
+        }
+
+        // Workers take the next tile as they finish one, so that a few
+        // crowded tiles don't hold the rest up.
+        let queue = Mutex::new(
+            bins.into_iter()
+                .enumerate()
+                .map(|(i, bin)| (i as PixCoord, bin))
+                .filter(|(_, bin)| !bin.is_empty())
+        );
+        let drawn = Mutex::new(Vec::new());
+        let (triangles, source) = (&self.triangles, &*texture);
+        thread::scope(|scope| {
+            for _ in 0 .. self.threads {
+                scope.spawn(|| loop {
+                    let next = queue.lock().unwrap().next();
+                    let (tile, bin) = match next {
+                        Some(tile) => tile,
+                        None => break,
+                    };
+                    let (column, row) = (tile % columns, tile / columns);
+                    let x = column as Dimension * TILE_SIZE;
+                    let y = row as Dimension * TILE_SIZE;
+                    let w = TILE_SIZE.min(source.w - x);
+                    let h = TILE_SIZE.min(source.h - y);
+                    let mut copy = source.copy_rect(x, y, w, h);
+                    let mut rect = copy.rect_at(x as PixCoord, y as PixCoord);
+                    for &i in &bin { triangles[i].draw(&mut rect) }
+                    drawn.lock().unwrap().push((x, y, copy));
+                });
+            }
+        });
+        for (x, y, rect) in drawn.into_inner().unwrap() {
+            texture.paste_rect(x, y, &rect);
+        }
+        self.triangles.clear();
+    }
+}
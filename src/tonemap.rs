@@ -0,0 +1,90 @@
+// Turning the linear, high dynamic range colors the renderer works in into
+// 8-bit pixels for the screen and image files: scale by exposure, compress
+// the range with a tone mapping curve, then gamma encode.
+
+use pixel::Color;
+use pixel::Pixel;
+use pixel::linear_to_srgb;
+use utils::clamp;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneOperator {
+    // Clips anything brighter than 1.
+    Clamp,
+    // c / (1 + c), which never quite reaches white.
+    Reinhard,
+    // Reinhard, scaled so that `white` maps to 1.
+    ReinhardExtended { white: f32 },
+    // Krzysztof Narkowicz's fit of the ACES filmic curve.
+    AcesFitted,
+}
+
+impl ToneOperator {
+    pub fn apply(&self, c: f32) -> f32 {
+        let c = c.max(0.);
+        match *self {
+            ToneOperator::Clamp => c.min(1.),
+            ToneOperator::Reinhard => c / (1. + c),
+            ToneOperator::ReinhardExtended { white } =>
+                c * (1. + c / (white * white)) / (1. + c),
+            ToneOperator::AcesFitted => {
+                let (a, b, c2, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                (c * (a * c + b) / (c * (c2 * c + d) + e)).min(1.)
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    // The piecewise sRGB curve, which is what screens expect.
+    Srgb,
+    // A plain power curve, c^(1 / gamma).
+    Gamma(f32),
+    // No encoding, for linear data.
+    Linear,
+}
+
+impl Encoding {
+    pub fn encode(&self, c: f32) -> u8 {
+        match *self {
+            Encoding::Srgb => linear_to_srgb(c),
+            Encoding::Gamma(gamma) =>
+                (clamp(c, 0., 1.).powf(1. / gamma) * 255.).round() as u8,
+            Encoding::Linear => (clamp(c, 0., 1.) * 255.).round() as u8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMapping {
+    // In stops: each one up doubles the brightness.
+    pub exposure: f32,
+    pub operator: ToneOperator,
+    pub encoding: Encoding,
+}
+
+impl ToneMapping {
+    // Clamps and sRGB encodes, so that colors decoded from pixels come back
+    // out as they went in.
+    pub fn new() -> ToneMapping {
+        ToneMapping {
+            exposure: 0.,
+            operator: ToneOperator::Clamp,
+            encoding: Encoding::Srgb,
+        }
+    }
+
+    pub fn map(&self, c: Color) -> Pixel {
+        let scale = self.exposure.exp2();
+        let channel = |c: f32| {
+            self.encoding.encode(self.operator.apply(c * scale))
+        };
+        (channel(c.r), channel(c.g), channel(c.b))
+    }
+}
+
+impl Default for ToneMapping {
+    fn default() -> ToneMapping { ToneMapping::new() }
+}
@@ -1,8 +1,21 @@
 use std::ops;
 
+use utils::clamp;
+
+// Coordinates are f64 unless the "f32" feature is on, which halves the
+// memory traffic of geometry and depth buffers at the cost of precision.
+// `coord` is the matching std module, for constants like coord::consts::PI.
+#[cfg(not(feature = "f32"))]
 pub type Coord = f64;
-pub type PixCoord = i16;
+#[cfg(not(feature = "f32"))]
+pub use std::f64 as coord;
+#[cfg(feature = "f32")]
+pub type Coord = f32;
+#[cfg(feature = "f32")]
+pub use std::f32 as coord;
+pub type PixCoord = i32;
 pub type Dimension = u32;
+pub type TexCoord = (Coord, Coord); // (u, v)
 
 
 #[derive(Clone, Copy)]
@@ -39,6 +52,85 @@ impl Triangle {
         let d2 = self.p3 - self.p1;
         d1.cross(d2).normalized()
     }
+
+    // Möller–Trumbore intersection. Hits both faces, and only counts hits
+    // in front of the ray's origin.
+    pub fn intersect_ray(self, ray: Ray) -> Option<RayHit> {
+        // The determinant scales with the edges and the direction, so it's
+        // compared against their lengths rather than a fixed epsilon.
+        const EPSILON: Coord = Coord::EPSILON * 64.;
+
+        let e1 = self.p2 - self.p1;
+        let e2 = self.p3 - self.p1;
+        let p = ray.direction.cross(e2);
+        let det = e1.dot(p);
+        let scale =
+            e1.magnitude() * e2.magnitude() * ray.direction.magnitude();
+        // Parallel to the plane, or degenerate.
+        if det.abs() <= EPSILON * scale { return None }
+
+        let inv_det = 1. / det;
+        let s = ray.origin - self.p1;
+        let u = s.dot(p) * inv_det;
+        if !(0. ..= 1.).contains(&u) { return None }
+
+        let q = s.cross(e1);
+        let v = ray.direction.dot(q) * inv_det;
+        if v < 0. || u + v > 1. { return None }
+
+        let t = e2.dot(q) * inv_det;
+        if t < 0. { return None }
+
+        Some(RayHit { t, u, v })
+    }
+
+    // Returns the point on the triangle nearest to p, following Ericson's
+    // Real-Time Collision Detection, 5.1.5.
+    pub fn closest_point(self, p: Point) -> Point {
+        let (a, b, c) = self.to_tuple();
+        let ab = b - a;
+        let ac = c - a;
+
+        // Vertex region A.
+        let ap = p - a;
+        let d1 = ab.dot(ap);
+        let d2 = ac.dot(ap);
+        if d1 <= 0. && d2 <= 0. { return a }
+
+        // Vertex region B.
+        let bp = p - b;
+        let d3 = ab.dot(bp);
+        let d4 = ac.dot(bp);
+        if d3 >= 0. && d4 <= d3 { return b }
+
+        // Edge region AB.
+        let vc = d1 * d4 - d3 * d2;
+        if vc <= 0. && d1 >= 0. && d3 <= 0. {
+            return a + ab * (d1 / (d1 - d3));
+        }
+
+        // Vertex region C.
+        let cp = p - c;
+        let d5 = ab.dot(cp);
+        let d6 = ac.dot(cp);
+        if d6 >= 0. && d5 <= d6 { return c }
+
+        // Edge region AC.
+        let vb = d5 * d2 - d1 * d6;
+        if vb <= 0. && d2 >= 0. && d6 <= 0. {
+            return a + ac * (d2 / (d2 - d6));
+        }
+
+        // Edge region BC.
+        let va = d3 * d6 - d5 * d4;
+        if va <= 0. && d4 - d3 >= 0. && d5 - d6 >= 0. {
+            return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+        }
+
+        // Inside the face.
+        let denom = 1. / (va + vb + vc);
+        a + ab * (vb * denom) + ac * (vc * denom)
+    }
 }
 
 impl ops::Mul<Transform> for Triangle {
@@ -54,7 +146,37 @@ impl ops::Mul<Transform> for Triangle {
 }
 
 
-const DIM: usize = 3;
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin:    Point,
+    pub direction: Point,
+}
+
+// Where a ray hit a triangle: the distance along the ray, in units of its
+// direction's length, and the barycentric weights of p2 and p3.
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    pub t: Coord,
+    pub u: Coord,
+    pub v: Coord,
+}
+
+impl Ray {
+    pub fn new(origin: Point, direction: Point) -> Ray {
+        Ray { origin, direction }
+    }
+
+    pub fn between(from: Point, to: Point) -> Ray {
+        Ray::new(from, to - from)
+    }
+
+    pub fn at(self, t: Coord) -> Point {
+        self.origin + self.direction * t
+    }
+}
+
+
+pub const DIM: usize = 3;
 
 #[derive(Copy, Clone, Debug)]
 pub struct Point {
@@ -84,7 +206,7 @@ impl Point {
         }
     }
 
-    pub fn from_array(arr: [f64; DIM + 1]) -> Point {
+    pub fn from_array(arr: [Coord; DIM + 1]) -> Point {
         Point {
             x: arr[0] / arr[DIM],
             y: arr[1] / arr[DIM],
@@ -92,16 +214,16 @@ impl Point {
         }
     }
 
-    pub fn to_array(self) -> [f64; DIM + 1] {
+    pub fn to_array(self) -> [Coord; DIM + 1] {
         [
-            self.x as f64,
-            self.y as f64,
-            self.z as f64,
+            self.x as Coord,
+            self.y as Coord,
+            self.z as Coord,
             1.
         ]
     }
 
-    pub fn dot(self, other: Point) -> f64 {
+    pub fn dot(self, other: Point) -> Coord {
         self.x * other.x +
         self.y * other.y +
         self.z * other.z
@@ -115,7 +237,7 @@ impl Point {
         ]
     }
 
-    pub fn magnitude(self) -> f64 {
+    pub fn magnitude(self) -> Coord {
         (
             self.x * self.x +
             self.y * self.y +
@@ -195,9 +317,251 @@ impl ops::Neg for Point {
     }
 }
 
+// A vector that transforms as a direction: translation doesn't move it and
+// there's no divide by w.
+#[derive(Copy, Clone, Debug)]
+pub struct Direction(pub Point);
+
+impl ops::Mul<Transform> for Direction {
+    type Output = Direction;
+
+    fn mul(self, rhs: Transform) -> Direction {
+        Direction(rhs.transform_direction(self.0))
+    }
+}
+
+// A rotation, stored as a unit quaternion w + xi + yj + zk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    pub w: Coord,
+    pub x: Coord,
+    pub y: Coord,
+    pub z: Coord,
+}
+
+impl Quaternion {
+    pub fn identity() -> Quaternion {
+        Quaternion { w: 1., x: 0., y: 0., z: 0. }
+    }
+
+    // A right-handed rotation of `angle` radians about `axis`.
+    pub fn from_axis_angle(axis: Point, angle: Coord) -> Quaternion {
+        let axis = axis.normalized();
+        let (sin, cos) = (angle / 2.).sin_cos();
+        Quaternion { w: cos, x: axis.x * sin, y: axis.y * sin, z: axis.z * sin }
+    }
+
+    // Returns the axis and angle of the rotation. The identity has no axis,
+    // and gives the x axis.
+    pub fn to_axis_angle(self) -> (Point, Coord) {
+        let q = self.normalized();
+        let q = if q.w < 0. { -q } else { q };
+        let sin = (1. - q.w * q.w).max(0.).sqrt();
+        if sin < 1e-12 { return (pt![1., 0., 0.], 0.) }
+        (pt![q.x / sin, q.y / sin, q.z / sin], 2. * q.w.min(1.).acos())
+    }
+
+    // These match Transform::rotate_x, rotate_y and rotate_z.
+    pub fn rotate_x(theta: Coord) -> Quaternion {
+        Quaternion::from_axis_angle(pt![1., 0., 0.], -theta)
+    }
+
+    pub fn rotate_y(theta: Coord) -> Quaternion {
+        Quaternion::from_axis_angle(pt![0., 1., 0.], theta)
+    }
+
+    pub fn rotate_z(theta: Coord) -> Quaternion {
+        Quaternion::from_axis_angle(pt![0., 0., 1.], -theta)
+    }
+
+    // Rotates about x, then y, then z, by the angles rotate_x, rotate_y and
+    // rotate_z take.
+    pub fn from_euler(x: Coord, y: Coord, z: Coord) -> Quaternion {
+        Quaternion::rotate_z(z)
+            * Quaternion::rotate_y(y)
+            * Quaternion::rotate_x(x)
+    }
+
+    // Inverts from_euler, with y in [-pi/2, pi/2].
+    pub fn to_euler(self) -> (Coord, Coord, Coord) {
+        let m = self.to_transform().data;
+        let y = clamp(-m[2][0], -1., 1.).asin();
+        if m[2][0].abs() < 1. - 1e-12 {
+            let x = (-m[2][1]).atan2(m[2][2]);
+            let z = (-m[1][0]).atan2(m[0][0]);
+            (x, y, z)
+        } else {
+            // Gimbal lock: only x + z or x - z is defined, so put it all in x.
+            (m[1][2].atan2(m[1][1]), y, 0.)
+        }
+    }
+
+    // Extracts the rotation from the upper 3x3 of a transform, which must
+    // be a pure rotation.
+    pub fn from_transform(t: Transform) -> Quaternion {
+        let m = t.data;
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        let q = if trace > 0. {
+            let s = 2. * (trace + 1.).sqrt();
+            Quaternion {
+                w: s / 4.,
+                x: (m[2][1] - m[1][2]) / s,
+                y: (m[0][2] - m[2][0]) / s,
+                z: (m[1][0] - m[0][1]) / s,
+            }
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = 2. * (1. + m[0][0] - m[1][1] - m[2][2]).sqrt();
+            Quaternion {
+                w: (m[2][1] - m[1][2]) / s,
+                x: s / 4.,
+                y: (m[0][1] + m[1][0]) / s,
+                z: (m[0][2] + m[2][0]) / s,
+            }
+        } else if m[1][1] > m[2][2] {
+            let s = 2. * (1. + m[1][1] - m[0][0] - m[2][2]).sqrt();
+            Quaternion {
+                w: (m[0][2] - m[2][0]) / s,
+                x: (m[0][1] + m[1][0]) / s,
+                y: s / 4.,
+                z: (m[1][2] + m[2][1]) / s,
+            }
+        } else {
+            let s = 2. * (1. + m[2][2] - m[0][0] - m[1][1]).sqrt();
+            Quaternion {
+                w: (m[1][0] - m[0][1]) / s,
+                x: (m[0][2] + m[2][0]) / s,
+                y: (m[1][2] + m[2][1]) / s,
+                z: s / 4.,
+            }
+        };
+        q.normalized()
+    }
+
+    pub fn to_transform(self) -> Transform {
+        let Quaternion { w, x, y, z } = self.normalized();
+        let mut t = Transform::identity();
+        t.data[0][0] = 1. - 2. * (y * y + z * z);
+        t.data[0][1] =      2. * (x * y - w * z);
+        t.data[0][2] =      2. * (x * z + w * y);
+        t.data[1][0] =      2. * (x * y + w * z);
+        t.data[1][1] = 1. - 2. * (x * x + z * z);
+        t.data[1][2] =      2. * (y * z - w * x);
+        t.data[2][0] =      2. * (x * z - w * y);
+        t.data[2][1] =      2. * (y * z + w * x);
+        t.data[2][2] = 1. - 2. * (x * x + y * y);
+        t
+    }
+
+    pub fn dot(self, other: Quaternion) -> Coord {
+        self.w * other.w +
+        self.x * other.x +
+        self.y * other.y +
+        self.z * other.z
+    }
+
+    pub fn magnitude(self) -> Coord {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalized(self) -> Quaternion {
+        let m = self.magnitude();
+        if m == 0. { return Quaternion::identity() }
+        self * (1. / m)
+    }
+
+    pub fn conjugate(self) -> Quaternion {
+        Quaternion { w: self.w, x: -self.x, y: -self.y, z: -self.z }
+    }
+
+    pub fn inverse(self) -> Quaternion {
+        self.conjugate() * (1. / self.dot(self))
+    }
+
+    pub fn rotate(self, p: Point) -> Point {
+        let v = Quaternion { w: 0., x: p.x, y: p.y, z: p.z };
+        let r = self * v * self.inverse();
+        pt![r.x, r.y, r.z]
+    }
+
+    // Interpolates along the shortest path at constant angular speed.
+    pub fn slerp(self, other: Quaternion, t: Coord) -> Quaternion {
+        let mut cos = self.dot(other);
+        let mut other = other;
+        if cos < 0. {
+            other = -other;
+            cos = -cos;
+        }
+        // Nearly parallel, where slerp's division is unstable.
+        if cos > 1. - 1e-9 { return self.nlerp(other, t) }
+
+        let angle = cos.acos();
+        let sin = angle.sin();
+        let a = ((1. - t) * angle).sin() / sin;
+        let b = (t * angle).sin() / sin;
+        (self * a + other * b).normalized()
+    }
+
+    // Interpolates linearly and renormalizes. Cheaper than slerp, but the
+    // speed varies over the interpolation.
+    pub fn nlerp(self, other: Quaternion, t: Coord) -> Quaternion {
+        let other = if self.dot(other) < 0. { -other } else { other };
+        (self * (1. - t) + other * t).normalized()
+    }
+}
+
+// Composition: (a * b) rotates by b, then by a, like Transform.
+impl ops::Mul for Quaternion {
+    type Output = Quaternion;
+
+    fn mul(self, r: Quaternion) -> Quaternion {
+        let l = self;
+        Quaternion {
+            w: l.w * r.w - l.x * r.x - l.y * r.y - l.z * r.z,
+            x: l.w * r.x + l.x * r.w + l.y * r.z - l.z * r.y,
+            y: l.w * r.y - l.x * r.z + l.y * r.w + l.z * r.x,
+            z: l.w * r.z + l.x * r.y - l.y * r.x + l.z * r.w,
+        }
+    }
+}
+
+impl ops::Mul<Coord> for Quaternion {
+    type Output = Quaternion;
+
+    fn mul(self, s: Coord) -> Quaternion {
+        Quaternion {
+            w: self.w * s,
+            x: self.x * s,
+            y: self.y * s,
+            z: self.z * s,
+        }
+    }
+}
+
+impl ops::Add for Quaternion {
+    type Output = Quaternion;
+
+    fn add(self, o: Quaternion) -> Quaternion {
+        Quaternion {
+            w: self.w + o.w,
+            x: self.x + o.x,
+            y: self.y + o.y,
+            z: self.z + o.z,
+        }
+    }
+}
+
+impl ops::Neg for Quaternion {
+    type Output = Quaternion;
+
+    fn neg(self) -> Quaternion {
+        Quaternion { w: -self.w, x: -self.x, y: -self.y, z: -self.z }
+    }
+}
+
+// A 4x4 matrix acting on column vectors, indexed as data[row][column].
 #[derive(Debug, Clone, Copy)]
 pub struct Transform {
-    data: [[f64; DIM + 1]; DIM + 1]
+    pub data: [[Coord; DIM + 1]; DIM + 1]
 }
 
 #[allow(dead_code)]
@@ -211,11 +575,11 @@ impl Transform {
     pub fn translate(off: Point) -> Transform {
         let mut t = Transform::identity();
         let arr_in = off.to_array();
-        for i in 0 .. DIM { t.data[i][DIM] = arr_in[i] as f64 }
+        for i in 0 .. DIM { t.data[i][DIM] = arr_in[i] as Coord }
         t
     }
 
-    pub fn rotate_x(theta: f64) -> Transform {
+    pub fn rotate_x(theta: Coord) -> Transform {
         let mut t = Transform::identity();
         t.data[1][1] =  theta.cos();
         t.data[1][2] =  theta.sin();
@@ -224,7 +588,7 @@ impl Transform {
         t
     }
 
-    pub fn rotate_y(theta: f64) -> Transform {
+    pub fn rotate_y(theta: Coord) -> Transform {
         let mut t = Transform::identity();
         t.data[0][0] =  theta.cos();
         t.data[0][2] =  theta.sin();
@@ -233,7 +597,7 @@ impl Transform {
         t
     }
 
-    pub fn rotate_z(theta: f64) -> Transform {
+    pub fn rotate_z(theta: Coord) -> Transform {
         let mut t = Transform::identity();
         t.data[0][0] =  theta.cos();
         t.data[0][1] =  theta.sin();
@@ -242,7 +606,7 @@ impl Transform {
         t
     }
 
-    pub fn scale(x: f64, y: f64, z: f64) -> Transform {
+    pub fn scale(x: Coord, y: Coord, z: Coord) -> Transform {
         let mut data = [[0.; DIM + 1]; DIM + 1];
         data[0][0] = x;
         data[1][1] = y;
@@ -251,6 +615,10 @@ impl Transform {
         Transform { data: data }
     }
 
+    pub fn row(&self, i: usize) -> [Coord; DIM + 1] {
+        self.data[i]
+    }
+
     pub fn perspective() -> Transform {
         let mut t = Transform::identity();
         t.data[DIM    ][DIM    ] =  0.;
@@ -258,6 +626,140 @@ impl Transform {
         t.data[DIM - 1][DIM - 1] = -1.;
         t
     }
+
+    pub fn from_rows(data: [[Coord; DIM + 1]; DIM + 1]) -> Transform {
+        Transform { data }
+    }
+
+    pub fn transpose(&self) -> Transform {
+        let mut t = *self;
+        for (i, row) in self.data.iter().enumerate() {
+            for (j, &v) in row.iter().enumerate() { t.data[j][i] = v }
+        }
+        t
+    }
+
+    pub fn determinant(&self) -> Coord {
+        let (s, c) = self.cofactor_terms();
+        s[0] * c[5] - s[1] * c[4] + s[2] * c[3]
+            + s[3] * c[2] - s[4] * c[1] + s[5] * c[0]
+    }
+
+    // Returns None if the matrix is singular.
+    pub fn inverse(&self) -> Option<Transform> {
+        let m = &self.data;
+        let (s, c) = self.cofactor_terms();
+        let det = self.determinant();
+        if det == 0. || !det.is_finite() { return None }
+
+        // The adjugate, from the 2x2 minors of the top and bottom halves.
+        let adj = [
+            [
+                 m[1][1] * c[5] - m[1][2] * c[4] + m[1][3] * c[3],
+                -m[0][1] * c[5] + m[0][2] * c[4] - m[0][3] * c[3],
+                 m[3][1] * s[5] - m[3][2] * s[4] + m[3][3] * s[3],
+                -m[2][1] * s[5] + m[2][2] * s[4] - m[2][3] * s[3],
+            ],
+            [
+                -m[1][0] * c[5] + m[1][2] * c[2] - m[1][3] * c[1],
+                 m[0][0] * c[5] - m[0][2] * c[2] + m[0][3] * c[1],
+                -m[3][0] * s[5] + m[3][2] * s[2] - m[3][3] * s[1],
+                 m[2][0] * s[5] - m[2][2] * s[2] + m[2][3] * s[1],
+            ],
+            [
+                 m[1][0] * c[4] - m[1][1] * c[2] + m[1][3] * c[0],
+                -m[0][0] * c[4] + m[0][1] * c[2] - m[0][3] * c[0],
+                 m[3][0] * s[4] - m[3][1] * s[2] + m[3][3] * s[0],
+                -m[2][0] * s[4] + m[2][1] * s[2] - m[2][3] * s[0],
+            ],
+            [
+                -m[1][0] * c[3] + m[1][1] * c[1] - m[1][2] * c[0],
+                 m[0][0] * c[3] - m[0][1] * c[1] + m[0][2] * c[0],
+                -m[3][0] * s[3] + m[3][1] * s[1] - m[3][2] * s[0],
+                 m[2][0] * s[3] - m[2][1] * s[1] + m[2][2] * s[0],
+            ],
+        ];
+
+        let mut data = [[0.; DIM + 1]; DIM + 1];
+        for (row, adj_row) in data.iter_mut().zip(adj.iter()) {
+            for (v, a) in row.iter_mut().zip(adj_row.iter()) {
+                *v = a / det;
+            }
+        }
+        Some(Transform { data })
+    }
+
+    // The 2x2 minors of the top two rows (s) and bottom two rows (c), which
+    // both the determinant and the inverse are built from.
+    fn cofactor_terms(&self) -> ([Coord; 6], [Coord; 6]) {
+        let m = &self.data;
+        let minor = |r: usize, a: usize, b: usize| {
+            m[r][a] * m[r + 1][b] - m[r + 1][a] * m[r][b]
+        };
+        let pairs = [(0, 1), (0, 2), (0, 3), (1, 2), (1, 3), (2, 3)];
+        let mut s = [0.; 6];
+        let mut c = [0.; 6];
+        for (k, &(a, b)) in pairs.iter().enumerate() {
+            s[k] = minor(0, a, b);
+            c[k] = minor(2, a, b);
+        }
+        (s, c)
+    }
+
+    // The matrix to transform normals by: the inverse-transpose of the
+    // upper 3x3, with no translation. Unlike the transform itself, this
+    // keeps normals perpendicular to their surfaces under non-uniform
+    // scaling. Returns None if the upper 3x3 is singular.
+    pub fn normal_matrix(&self) -> Option<Transform> {
+        let mut linear = Transform::identity();
+        for i in 0 .. DIM {
+            linear.data[i][.. DIM].copy_from_slice(&self.data[i][.. DIM]);
+        }
+        linear.inverse().map(|inv| inv.transpose())
+    }
+
+    // Applies the transform to a direction, ignoring translation and the
+    // projective row.
+    pub fn transform_direction(&self, d: Point) -> Point {
+        let v = d.to_array();
+        let row = |i: usize| -> Coord {
+            self.data[i][.. DIM].iter().zip(v.iter()).map(|(a, b)| a * b).sum()
+        };
+        pt![row(0), row(1), row(2)]
+    }
+
+    // Like transform_direction, for a surface normal, and normalized. Falls
+    // back to the plain transform when the normal matrix doesn't exist.
+    pub fn transform_normal(&self, n: Point) -> Point {
+        let m = self.normal_matrix().unwrap_or(*self);
+        m.transform_direction(n).normalized()
+    }
+
+    // Splits an affine transform into translation, rotation and scale, so
+    // that it equals translate(t) * r.to_transform() * scale(s). Shear and
+    // any projective part are lost. A mirroring transform comes back with
+    // a negative x scale.
+    pub fn decompose(&self) -> (Point, Quaternion, Point) {
+        let m = &self.data;
+        let translation = pt![m[0][DIM], m[1][DIM], m[2][DIM]];
+
+        let column = |j: usize| pt![m[0][j], m[1][j], m[2][j]];
+        let mut scale = pt![
+            column(0).magnitude(),
+            column(1).magnitude(),
+            column(2).magnitude()
+        ];
+        if self.determinant() < 0. { scale.x = -scale.x }
+
+        let mut rotation = Transform::identity();
+        let scales = scale.to_array();
+        for (row, m_row) in rotation.data.iter_mut().zip(m.iter()).take(DIM) {
+            for j in (0 .. DIM).filter(|&j| scales[j] != 0.) {
+                row[j] = m_row[j] / scales[j];
+            }
+        }
+        (translation, Quaternion::from_transform(rotation), scale)
+    }
 }
 
 impl ops::Mul for Transform {
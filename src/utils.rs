@@ -5,3 +5,17 @@ pub fn clamp<Num: PartialOrd>(x: Num, min: Num, max: Num)
     else if x > max { max }
     else { x }
 }
+
+// Copies a w by h rectangle between row-major buffers of the given widths,
+// from (x, y) in one to (to_x, to_y) in the other.
+pub fn copy_rect<T: Copy>(
+    from: &[T], from_w: usize, (x, y): (usize, usize),
+    to: &mut [T], to_w: usize, (to_x, to_y): (usize, usize),
+    (w, h): (usize, usize),
+) {
+    for row in 0 .. h {
+        let i = (y + row) * from_w + x;
+        let j = (to_y + row) * to_w + to_x;
+        to[j .. j + w].copy_from_slice(&from[i .. i + w]);
+    }
+}
@@ -0,0 +1,194 @@
+#[macro_use] extern crate rusterize;
+
+use rusterize::animation::Clip;
+use rusterize::animation::Interpolation;
+use rusterize::animation::PlayMode;
+use rusterize::animation::Pose;
+use rusterize::animation::Track;
+use rusterize::object::Object;
+use rusterize::primitives;
+use rusterize::skinning::Influences;
+use rusterize::skinning::Joint;
+use rusterize::skinning::Skeleton;
+use rusterize::skinning::SkinnedMesh;
+use rusterize::types::*;
+
+
+fn close(a: Point, b: Point) -> bool { (a - b).magnitude() < 1e-4 }
+
+// Keyframes along x, evenly spaced in time and distance, with a bump in y
+// at t = 2.
+fn track(interpolation: Interpolation) -> Track<Point> {
+    Track::from_keyframes(interpolation, &[
+        (0., pt![0., 0., 0.]),
+        (1., pt![2., 0., 0.]),
+        (2., pt![4., 1., 0.]),
+        (3., pt![6., 0., 0.]),
+    ])
+}
+
+#[test]
+fn step_holds_each_keyframe() {
+    let track = track(Interpolation::Step);
+    assert!(close(track.sample(0.99).unwrap(), pt![0., 0., 0.]));
+    assert!(close(track.sample(1.).unwrap(), pt![2., 0., 0.]));
+    assert!(close(track.sample(2.5).unwrap(), pt![4., 1., 0.]));
+    assert!(close(track.sample(9.).unwrap(), pt![6., 0., 0.]));
+    assert!(Track::<Point>::new(Interpolation::Step).sample(0.).is_none());
+}
+
+#[test]
+fn linear_goes_straight_between_keyframes() {
+    let track = track(Interpolation::Linear);
+    assert!(close(track.sample(0.25).unwrap(), pt![0.5, 0., 0.]));
+    assert!(close(track.sample(1.5).unwrap(), pt![3., 0.5, 0.]));
+    assert!(close(track.sample(-1.).unwrap(), pt![0., 0., 0.]));
+
+    // Rotations take the short way round, even from a negated keyframe.
+    let half_turn = Quaternion::from_euler(0., 3., 0.);
+    let rotations = Track::from_keyframes(Interpolation::Linear, &[
+        (0., Quaternion::identity()),
+        (1., -half_turn),
+    ]);
+    let halfway = rotations.sample(0.5).unwrap();
+    let expected = Quaternion::from_euler(0., 1.5, 0.);
+    assert!(halfway.dot(expected).abs() > 1. - 1e-4);
+}
+
+#[test]
+fn catmull_rom_passes_through_keyframes_smoothly() {
+    let smooth = track(Interpolation::CatmullRom);
+    for k in smooth.keyframes() {
+        assert!(close(smooth.sample(k.time).unwrap(), k.value));
+    }
+
+    // Along x the keyframes are on a line, which the curve follows.
+    for &t in &[0.3, 1.25, 2.7] {
+        assert!((smooth.sample(t).unwrap().x - 2. * t).abs() < 1e-4);
+    }
+
+    // No corner at the bump, unlike with linear interpolation.
+    let h = 1e-3;
+    let slope = |track: &Track<Point>, a: Coord, b: Coord| {
+        (track.sample(b).unwrap().y - track.sample(a).unwrap().y) / (b - a)
+    };
+    let before = slope(&smooth, 2. - 2. * h, 2. - h);
+    let after = slope(&smooth, 2. + h, 2. + 2. * h);
+    assert!((before - after).abs() < 0.05, "{} {}", before, after);
+    let linear = track(Interpolation::Linear);
+    let before = slope(&linear, 2. - 2. * h, 2. - h);
+    let after = slope(&linear, 2. + h, 2. + 2. * h);
+    assert!((before - after).abs() > 1.9);
+}
+
+fn clip(mode: PlayMode) -> Clip {
+    let mut clip = Clip::new(mode);
+    clip.translation = Some(Track::from_keyframes(Interpolation::Linear, &[
+        (0., pt![0., 0., 0.]),
+        (2., pt![4., 0., 0.]),
+    ]));
+    clip
+}
+
+fn x_at(clip: &Clip, time: Coord) -> Coord {
+    clip.sample(time, &Pose::identity()).translation.x
+}
+
+#[test]
+fn play_modes_wrap_time() {
+    let once = clip(PlayMode::Once);
+    assert!((x_at(&once, -1.) - 0.).abs() < 1e-4);
+    assert!((x_at(&once, 1.) - 2.).abs() < 1e-4);
+    assert!((x_at(&once, 5.) - 4.).abs() < 1e-4);
+
+    // Loop starts over, before the start too.
+    let looped = clip(PlayMode::Loop);
+    for &(time, x) in &[(0.5, 1.), (2.5, 1.), (4.5, 1.), (-0.5, 3.)] {
+        assert!((x_at(&looped, time) - x).abs() < 1e-4, "{}", time);
+    }
+
+    // PingPong comes back the way it went.
+    let ping_pong = clip(PlayMode::PingPong);
+    for &(time, x) in &[(0.5, 1.), (2.5, 3.), (3.5, 1.), (4.5, 1.), (-0.5, 1.)]
+    {
+        assert!((x_at(&ping_pong, time) - x).abs() < 1e-4, "{}", time);
+    }
+
+    // Parts without a track come from the base pose.
+    let base = Pose { scale: pt![2., 2., 2.], ..Pose::identity() };
+    assert!(close(looped.sample(0.5, &base).scale, pt![2., 2., 2.]));
+}
+
+// An upright tube from y = -2 to 2, bending at an elbow joint at the
+// origin. Vertices below y = -0.5 follow the root, those above 0.5 the
+// elbow, and those between a blend of the two.
+fn arm() -> (Skeleton, SkinnedMesh) {
+    let root = Pose { translation: pt![0., -2., 0.], ..Pose::identity() };
+    let elbow = Pose { translation: pt![0., 2., 0.], ..Pose::identity() };
+    let mut skeleton = Skeleton::new(vec![
+        Joint::new("root", None, root),
+        Joint::new("elbow", Some(0), elbow),
+    ]);
+    skeleton.set_bind_pose();
+
+    let tube = primitives::cylinder(0.25, 4., 8, 8);
+    let influences = |p: Point| {
+        let w = (p.y + 0.5).clamp(0., 1.);
+        Influences::new([0, 1, 0, 0], [1. - w, w, 0., 0.])
+    };
+    let weights = tube.triangles().iter()
+        .map(|t| [influences(t.p1), influences(t.p2), influences(t.p3)])
+        .collect();
+    (skeleton, SkinnedMesh::new(tube, weights))
+}
+
+fn points(object: &Object) -> Vec<Point> {
+    object.triangles().iter().flat_map(|t| vec![t.p1, t.p2, t.p3]).collect()
+}
+
+#[test]
+fn bind_pose_reproduces_the_mesh() {
+    let (skeleton, mut mesh) = arm();
+    let bind = mesh.object().clone();
+    mesh.update(&skeleton);
+    for (&a, &b) in points(mesh.object()).iter().zip(&points(&bind)) {
+        assert!(close(a, b), "{:?} moved to {:?}", b, a);
+    }
+    let normals = mesh.object().normals().unwrap();
+    let bind_normals = bind.normals().unwrap();
+    for (a, b) in normals.iter().zip(bind_normals) {
+        assert!((0 .. 3).all(|i| close(a[i], b[i])));
+    }
+}
+
+#[test]
+fn skinned_vertices_follow_their_joints() {
+    let (mut skeleton, mut mesh) = arm();
+    let bind = points(mesh.object());
+
+    // Turning the root moves the whole arm rigidly, elbow and all.
+    let turn = Quaternion::from_euler(0., 0., 0.5);
+    skeleton.joints[0].pose.rotation = turn;
+    mesh.update(&skeleton);
+    let about_root = Transform::translate(pt![0., -2., 0.])
+        * turn.to_transform() * Transform::translate(pt![0., 2., 0.]);
+    for (&a, &b) in points(mesh.object()).iter().zip(&bind) {
+        assert!(close(a, b * about_root), "{:?} moved to {:?}", b, a);
+    }
+
+    // Bending the elbow leaves the root's vertices alone.
+    skeleton.joints[0].pose.rotation = Quaternion::identity();
+    let bend = Quaternion::from_euler(0., 0., 1.2);
+    skeleton.joints[1].pose.rotation = bend;
+    mesh.update(&skeleton);
+    for (&a, &b) in points(mesh.object()).iter().zip(&bind) {
+        let expected = if b.y < -0.5 {
+            b
+        } else if b.y > 0.5 {
+            b * bend.to_transform()
+        } else {
+            continue
+        };
+        assert!(close(a, expected), "{:?} moved to {:?}", b, a);
+    }
+}
@@ -0,0 +1,165 @@
+extern crate rusterize;
+
+use std::env;
+use std::fs;
+use std::process;
+
+use rusterize::format::Gray8;
+use rusterize::format::PixelFormat;
+use rusterize::format::R32f;
+use rusterize::format::Rgb565;
+use rusterize::format::Rgb8;
+use rusterize::format::Rgba8;
+use rusterize::pixel::Color;
+use rusterize::pixel::Rgba;
+use rusterize::pixel::linear_to_srgb;
+use rusterize::pixel::srgb_to_linear;
+use rusterize::terrain::Heightmap;
+use rusterize::texture::Texture;
+use rusterize::tonemap::Encoding;
+use rusterize::tonemap::ToneMapping;
+use rusterize::tonemap::ToneOperator;
+use rusterize::types::Coord;
+
+
+// The size of one step of a channel with the given bits, in 8-bit units.
+fn lsb(bits: u32) -> i32 { (255 + (1 << bits) - 2) / ((1 << bits) - 1) }
+
+fn within(a: u8, b: u8, tolerance: i32) -> bool {
+    (a as i32 - b as i32).abs() <= tolerance
+}
+
+#[test]
+fn rgb565_round_trips_within_a_step() {
+    for v in 0 ..= 255 {
+        for &p in &[(v, 0, 0), (0, v, 0), (0, 0, v), (v, v, v)] {
+            let (r, g, b): Rgb8 = p.convert::<Rgb565>().convert();
+            assert!(within(r, p.0, lsb(5)), "{:?} -> {:?}", p, (r, g, b));
+            assert!(within(g, p.1, lsb(6)), "{:?} -> {:?}", p, (r, g, b));
+            assert!(within(b, p.2, lsb(5)), "{:?} -> {:?}", p, (r, g, b));
+        }
+    }
+}
+
+#[test]
+fn rgba8_keeps_alpha() {
+    for a in 0 ..= 255 {
+        let p = Rgba8 { r: 200, g: 100, b: 7, a };
+        assert_eq!(p.convert::<Rgba8>(), p);
+        assert!((p.to_rgba().a - a as f32 / 255.).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn gray_formats_keep_luminance() {
+    for &p in &[(255, 0, 0), (0, 255, 0), (0, 0, 255), (30, 140, 220)] {
+        let p: Rgb8 = p;
+        let luminance = Rgba::from_pixel(p, 255).color().luminance();
+
+        let R32f(linear) = p.convert();
+        assert!((linear - luminance).abs() < 1e-5, "{:?}", p);
+
+        let Gray8(encoded) = p.convert();
+        assert!((srgb_to_linear(encoded) - luminance).abs() < 0.01);
+        let (r, g, b): Rgb8 = Gray8(encoded).convert();
+        assert_eq!((r, g, b), (encoded, encoded, encoded));
+
+        let R32f(back) = Gray8(encoded).convert();
+        assert!((back - srgb_to_linear(encoded)).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn converting_textures_keeps_depths() {
+    let mut texture: Texture<Rgb8> = Texture::new(4, 3);
+    texture.set_pixel(1, 1, 2.5, (255, 0, 0));
+    texture.set_pixel(3, 2, 7., (0, 255, 0));
+    let converted: Texture<Rgb565> = texture.convert();
+    let (before, after) = (texture.depth(), converted.depth());
+    for y in 0 .. 3 {
+        for x in 0 .. 4 {
+            assert_eq!(before.get(x, y), after.get(x, y));
+        }
+    }
+    assert_eq!(after.get(1, 1), 2.5);
+    assert_eq!(after.get(0, 0), Coord::INFINITY);
+}
+
+#[test]
+fn images_larger_than_their_data_are_rejected() {
+    let path = env::temp_dir()
+        .join(format!("rusterize-{}-huge.pgm", process::id()));
+    let path = path.to_str().unwrap();
+    let load = |contents: &[u8]| {
+        fs::write(path, contents).unwrap();
+        Heightmap::from_file(path)
+    };
+
+    assert!(load(b"P5 2 1 255\n\x00\xff").is_ok());
+    assert!(load(b"P2 2 1 255\n0 255\n").is_ok());
+    let headers: [&[u8]; 4] = [
+        b"P5 2 2 255\n\x00\xff",
+        b"P6 4294967295 4294967295 255\n",
+        b"P5 99999 99999 65535\n\x00",
+        b"P3 99999 99999 255\n0",
+    ];
+    for header in &headers {
+        let error = load(header).err().unwrap();
+        assert!(error.to_string().contains("larger than its data"),
+                "{:?}: {}", String::from_utf8_lossy(header), error);
+    }
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn srgb_round_trips_through_tone_mapping() {
+    let identity = ToneMapping::new();
+    let mut previous = -1.;
+    for v in 0 ..= 255 {
+        let linear = srgb_to_linear(v);
+        assert!(linear > previous, "{}", v);
+        previous = linear;
+        assert_eq!(linear_to_srgb(linear), v);
+        let p = (v, 255 - v, v / 2);
+        assert_eq!(identity.map(Color::from_srgb(p)), p);
+    }
+
+    // Through a texture too.
+    let mut texture: Texture = Texture::new(16, 16);
+    for y in 0 .. 16 {
+        for x in 0 .. 16 {
+            let v = (y * 16 + x) as u8;
+            texture.set_pixel(x, y, 0., (v, v, 255 - v));
+        }
+    }
+    let pixels = texture.to_pixels(&identity);
+    for (i, &p) in pixels.iter().enumerate() {
+        let v = i as u8;
+        assert_eq!(p, (v, v, 255 - v));
+    }
+}
+
+#[test]
+fn tone_mapping_compresses_highlights() {
+    let mapping = |operator, exposure| ToneMapping {
+        exposure,
+        operator,
+        encoding: Encoding::Linear,
+    };
+    // Reinhard keeps brighter brighter, but never reaches white.
+    let reinhard = ToneOperator::Reinhard;
+    let levels: Vec<f32> = [1., 4., 16., 1000.].iter()
+        .map(|&c| reinhard.apply(c))
+        .collect();
+    assert!(levels.windows(2).all(|w| w[0] < w[1] && w[1] < 1.));
+
+    let bright = Color::new(4., 16., 1000.);
+    let white = ToneOperator::ReinhardExtended { white: 16. };
+    assert_eq!(mapping(white, 0.).map(bright).1, 255);
+    assert_eq!(mapping(ToneOperator::AcesFitted, 0.).map(bright).2, 255);
+
+    // Each stop of exposure doubles the light.
+    let gray = Color::new(0.1, 0.1, 0.1);
+    assert_eq!(mapping(ToneOperator::Clamp, 1.).map(gray).0,
+               mapping(ToneOperator::Clamp, 0.).map(gray * 2.).0);
+}
@@ -0,0 +1,339 @@
+#[macro_use] extern crate rusterize;
+
+use std::collections::HashMap;
+
+use rusterize::bounds::Aabb;
+use rusterize::bvh::Bvh;
+use rusterize::isosurface;
+use rusterize::lod::LodSet;
+use rusterize::mesh::IndexedMesh;
+use rusterize::mesh::PolyMesh;
+use rusterize::object::Object;
+use rusterize::primitives;
+use rusterize::simplify::Target;
+use rusterize::subdivide;
+use rusterize::subdivide::Creases;
+use rusterize::terrain;
+use rusterize::terrain::TerrainSpec;
+use rusterize::types::coord::consts::PI;
+use rusterize::types::*;
+
+
+// A right triangle in the z = 0 plane with legs of the given length.
+fn triangle(size: Coord) -> Triangle {
+    trigon![pt![0., 0., 0.], pt![size, 0., 0.], pt![0., size, 0.]]
+}
+
+#[test]
+fn ray_hits_triangles_of_any_scale() {
+    for &size in &[1e-7, 1e-3, 1., 1e3, 1e7] {
+        let ray = Ray::new(pt![size / 4., size / 4., size], pt![0., 0., -1.]);
+        let hit = triangle(size).intersect_ray(ray).unwrap();
+        assert!((hit.t - size).abs() <= size * 1e-4);
+        assert!((hit.u - 0.25).abs() < 1e-4 && (hit.v - 0.25).abs() < 1e-4);
+    }
+}
+
+#[test]
+fn ray_parallel_to_triangle_misses() {
+    for &size in &[1e-6, 1., 1e6] {
+        let ray = Ray::new(pt![-size, size / 4., 0.], pt![1., 0., 0.]);
+        assert!(triangle(size).intersect_ray(ray).is_none());
+    }
+    let degenerate = trigon![pt![0., 0., 0.], pt![1., 0., 0.], pt![2., 0., 0.]];
+    let ray = Ray::new(pt![0.5, 0., 1.], pt![0., 0., -1.]);
+    assert!(degenerate.intersect_ray(ray).is_none());
+}
+
+#[test]
+fn surface_points_dont_block_themselves() {
+    let object = Object::new(vec![triangle(1.)]);
+    let on_surface = pt![0.25, 0.25, 0.];
+    assert!(!object.blocks(on_surface, pt![0.25, 0.25, 1.]));
+    assert!(!object.blocks(on_surface, pt![0.25, 0.25, -1.]));
+    assert!(object.blocks(pt![0.25, 0.25, 1.], pt![0.25, 0.25, -1.]));
+}
+
+#[test]
+fn hierarchy_queries_follow_the_object() {
+    // A strip of triangles along x.
+    let triangles: Vec<Triangle> = (0 .. 32)
+        .map(|i| {
+            let x = i as Coord;
+            trigon![pt![x, 0., 0.], pt![x + 1., 0., 0.], pt![x, 1., 0.]]
+        })
+        .collect();
+    let mut plain = Object::new(triangles.clone());
+    let mut with_bvh = Object::new(triangles).with_bvh();
+    for object in [&mut plain, &mut with_bvh] {
+        object.translate(pt![-16., 0., 0.]);
+        object.rotate_z(0.5);
+        object.translate(pt![0., 2., -1.]);
+    }
+
+    // Down through points along the strip, and every fifth one beside it.
+    let world = plain.pose().to_transform();
+    for i in 0 .. 64 {
+        let across = if i % 5 == 0 { 2. } else { 0.2 };
+        let p = pt![i as Coord * 0.5 + 0.25, across, 0.] * world;
+        let (from, to) = (p + pt![0., 0., 3.], p + pt![0., 0., -3.]);
+        let a = plain.intersect_ray(Ray::between(from, to));
+        let b = with_bvh.intersect_ray(Ray::between(from, to));
+        assert_eq!(a.map(|h| h.triangle_index), b.map(|h| h.triangle_index));
+        assert_eq!(plain.blocks(from, to), with_bvh.blocks(from, to));
+    }
+}
+
+#[test]
+fn lod_levels_share_the_base_id() {
+    let triangles: Vec<Triangle> = (0 .. 16)
+        .map(|i| {
+            let x = i as Coord;
+            trigon![pt![x, 0., 0.], pt![x + 1., 0., 0.], pt![x, 1., 0.]]
+        })
+        .collect();
+    let base = Object::new(triangles);
+    let id = base.id();
+    let coarse = base.clone();
+    let mut set = LodSet::from_object(base, &[(8, 100.), (4, 10.)]);
+    set.add_level(coarse, 1.);
+    assert_eq!(set.levels().len(), 4);
+    assert!(set.levels().iter().all(|l| l.object.id() == id));
+}
+
+#[test]
+fn directions_ignore_translation() {
+    let t = Transform::translate(pt![5., -3., 2.]) * Transform::rotate_z(0.5)
+        * Transform::scale(2., 2., 2.);
+    let d = pt![1., 0., 0.];
+    let Direction(moved) = Direction(d) * t;
+    let expected = (pt![1., 0., 0.] * t) - (pt![0., 0., 0.] * t);
+    assert!((moved - expected).magnitude() < 1e-4);
+    assert!((moved - t.transform_direction(d)).magnitude() < 1e-6);
+}
+
+#[test]
+fn hierarchy_splits_clustered_items() {
+    // Two clusters of small boxes with a wide gap between them.
+    let mut seed: u32 = 7;
+    let mut random = move || {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        (seed >> 8) as Coord / (1 << 24) as Coord
+    };
+    let bounds: Vec<Aabb> = (0 .. 2000)
+        .map(|i| {
+            let offset = if i % 2 == 0 { 0. } else { 1000. };
+            let p = pt![offset + random(), random(), random()];
+            Aabb::from_points(vec![p, p + pt![0.01, 0.01, 0.01]])
+        })
+        .collect();
+    let bvh = Bvh::build(&bounds);
+
+    let rays = 200;
+    let mut tested = 0;
+    // Down through the middle of every tenth box, so each ray hits.
+    for i in 0 .. rays {
+        let target = bounds[i * 10].center();
+        let ray = Ray::new(pt![target.x, target.y, 5.], pt![0., 0., -1.]);
+        let hit = bvh.intersect_ray(ray, |item| {
+            tested += 1;
+            bounds[item].intersect_ray(ray).map(|(t, _)| t)
+        });
+        assert!(hit.is_some());
+    }
+    assert!(tested < rays * 10, "{} items tested per ray", tested / rays);
+}
+
+#[test]
+fn simplifying_flat_meshes_is_repeatable_and_on_target() {
+    let plane = primitives::plane(10., 10., 16, 16);
+    let corners = |o: &Object| -> Vec<[Coord; 9]> {
+        o.triangles().iter()
+            .map(|t| [t.p1.x, t.p1.y, t.p1.z, t.p2.x, t.p2.y, t.p2.z,
+                      t.p3.x, t.p3.y, t.p3.z])
+            .collect()
+    };
+    for &target in &[100, 99, 37] {
+        let first = corners(&plane.simplified(Target::triangles(target)));
+        assert_eq!(first.len(), target);
+        for _ in 0 .. 3 {
+            let again = corners(&plane.simplified(Target::triangles(target)));
+            assert_eq!(again, first);
+        }
+    }
+}
+
+// A cube from -1 to 1 as six quads, wound outward.
+fn cube_cage() -> PolyMesh {
+    let corner = |i: usize| {
+        let c = |bit: usize| if i & bit == 0 { -1. } else { 1. };
+        pt![c(1), c(2), c(4)]
+    };
+    PolyMesh {
+        positions: (0 .. 8).map(corner).collect(),
+        faces: vec![
+            vec![0, 2, 3, 1], vec![4, 5, 7, 6], vec![0, 1, 5, 4],
+            vec![2, 6, 7, 3], vec![0, 4, 6, 2], vec![1, 3, 7, 5],
+        ],
+    }
+}
+
+// Every edge is shared by exactly two faces.
+fn is_closed(mesh: &PolyMesh) -> bool {
+    let triangles = mesh.to_triangles();
+    IndexedMesh::from_triangles(&triangles).edge_face_counts()
+        .values().all(|&n| n == 2)
+}
+
+#[test]
+fn loop_subdivision_splits_every_triangle_in_four() {
+    let ico = primitives::icosphere(1., 0);
+    let mesh = PolyMesh::from_triangles(ico.triangles());
+    assert_eq!((mesh.positions.len(), mesh.faces.len()), (12, 20));
+
+    // Each level adds a vertex per edge.
+    let once = subdivide::loop_subdivide(&mesh, 1, &Creases::new());
+    assert_eq!((once.positions.len(), once.faces.len()), (12 + 30, 80));
+    let twice = subdivide::loop_subdivide(&mesh, 2, &Creases::new());
+    assert_eq!((twice.positions.len(), twice.faces.len()), (42 + 120, 320));
+    assert!(twice.faces.iter().all(|f| f.len() == 3));
+    assert!(is_closed(&twice));
+}
+
+#[test]
+fn catmull_clark_makes_quads_and_keeps_creases() {
+    let cube = cube_cage();
+
+    // Each level adds a vertex per edge and per face.
+    let smooth = subdivide::catmull_clark(&cube, 2, &Creases::new());
+    assert_eq!(smooth.positions.len(), 8 + 12 + 6 + 48 + 24);
+    assert_eq!(smooth.faces.len(), 96);
+    assert!(smooth.faces.iter().all(|f| f.len() == 4));
+    assert!(is_closed(&smooth));
+
+    let on_surface = |p: Point| {
+        let m = p.x.abs().max(p.y.abs()).max(p.z.abs());
+        (m - 1.).abs() < 1e-6
+    };
+    let is_corner = |p: Point| {
+        (p.x.abs() - 1.).abs() < 1e-6 && (p.y.abs() - 1.).abs() < 1e-6
+            && (p.z.abs() - 1.).abs() < 1e-6
+    };
+    // Smoothed, the corners are rounded off.
+    assert!(!smooth.positions.iter().any(|&p| is_corner(p)));
+
+    // With every edge a crease, the cube keeps its shape.
+    let creases = subdivide::creases_by_angle(&cube, 0.5);
+    assert_eq!(creases.len(), 12);
+    let sharp = subdivide::catmull_clark(&cube, 2, &creases);
+    assert_eq!(sharp.positions.len(), smooth.positions.len());
+    assert!(sharp.positions.iter().all(|&p| on_surface(p)));
+    assert_eq!(sharp.positions.iter().filter(|&&p| is_corner(p)).count(), 8);
+}
+
+// Positive when the triangles of a closed mesh face outward, and a little
+// under the volume of the shape they approximate.
+fn signed_volume(triangles: &[Triangle]) -> Coord {
+    triangles.iter()
+        .map(|t| t.p1.dot(t.p2.cross(t.p3)) / 6.)
+        .sum()
+}
+
+#[test]
+fn primitives_face_outward_along_their_normals() {
+    let closed = [
+        ("cuboid", primitives::cuboid(2., 3., 4., 2), 24.),
+        ("uv_sphere", primitives::uv_sphere(1., 16, 8), 4. / 3. * PI),
+        ("icosphere", primitives::icosphere(1., 2), 4. / 3. * PI),
+        ("cylinder", primitives::cylinder(1., 2., 16, 2), 2. * PI),
+        ("cone", primitives::cone(1., 2., 16, 2), 2. / 3. * PI),
+        ("torus", primitives::torus(2., 0.5, 24, 12), PI * PI),
+        ("capsule", primitives::capsule(1., 2., 16, 4), 10. / 3. * PI),
+    ];
+    for &(name, ref object, volume) in &closed {
+        let triangles = object.triangles();
+        let counts = IndexedMesh::from_triangles(triangles).edge_face_counts();
+        assert!(counts.values().all(|&n| n == 2), "{} has holes", name);
+        let v = signed_volume(triangles);
+        assert!((v - volume).abs() < volume * 0.1, "{}: {}", name, v);
+    }
+
+    let flat = [primitives::plane(2., 2., 3, 3), primitives::disc(1., 12, 2)];
+    let all = closed.iter().map(|c| &c.1).chain(flat.iter());
+    for object in all {
+        let normals = object.normals().unwrap();
+        for (t, n) in object.triangles().iter().zip(normals) {
+            let face = (t.p2 - t.p1).cross(t.p3 - t.p1);
+            assert!(n.iter().all(|&n| face.dot(n) > 0.), "{:?}", t.p1);
+        }
+    }
+    for object in &flat {
+        assert!(object.triangles().iter().all(|t| t.normal().y > 0.99));
+    }
+}
+
+#[test]
+fn terrain_tiles_meet_without_seams() {
+    let spec = TerrainSpec {
+        width: 10., depth: 8., height: 1., cells_x: 13, cells_z: 7,
+    };
+    let bumps = |x: Coord, z: Coord| (x * 0.7).sin() * (z * 1.3).cos();
+    let whole = terrain::from_fn(bumps, &spec);
+    // Tiles that don't divide the cells evenly.
+    let tiles = terrain::tiles_from_fn(bumps, &spec, 3, 2);
+    assert_eq!(tiles.len(), 6);
+
+    let triangles: Vec<Triangle> = tiles.iter()
+        .flat_map(|t| t.triangles().iter().cloned())
+        .collect();
+    assert_eq!(triangles.len(), whole.triangles().len());
+
+    // Welded together, the tiles make one grid, open only around its rim.
+    let mesh = IndexedMesh::from_triangles(&triangles);
+    assert_eq!(mesh.positions.len(), 14 * 8);
+    let counts = mesh.edge_face_counts();
+    assert!(counts.values().all(|&n| n <= 2));
+    let open = counts.values().filter(|&&n| n == 1).count();
+    assert_eq!(open, 2 * (13 + 7));
+
+    // Vertices on a seam have the same normal in every tile.
+    let key = |p: Point| (p.x.to_bits(), p.y.to_bits(), p.z.to_bits());
+    let mut normals = HashMap::new();
+    for tile in &tiles {
+        let tile_normals = tile.normals().unwrap();
+        for (t, n) in tile.triangles().iter().zip(tile_normals) {
+            for (&p, &n) in [t.p1, t.p2, t.p3].iter().zip(n) {
+                let first = *normals.entry(key(p)).or_insert(n);
+                assert!((first - n).magnitude() < 1e-6, "{:?}", p);
+            }
+        }
+    }
+}
+
+#[test]
+fn marching_cubes_spheres_are_watertight() {
+    let center = pt![0.13, -0.07, 0.21];
+    let sphere = |p: Point| (p - center).magnitude() - 1.;
+    for &resolution in &[8, 17, 32] {
+        let object = isosurface::marching_cubes_fn(
+            sphere, pt![-1.5, -1.5, -1.5], pt![1.5, 1.5, 1.5], resolution,
+        );
+        let triangles = object.triangles();
+        let counts = IndexedMesh::from_triangles(triangles).edge_face_counts();
+        assert!(counts.values().all(|&n| n == 2), "holes at {}", resolution);
+
+        // Around the sphere, facing out.
+        let moved: Vec<Triangle> = triangles.iter()
+            .map(|&t| t * Transform::translate(center * -1.))
+            .collect();
+        let volume = signed_volume(&moved);
+        assert!((volume - 4. / 3. * PI).abs() < 0.5, "{}", volume);
+        let normals = object.normals().unwrap();
+        for (t, n) in moved.iter().zip(normals) {
+            for (&p, &n) in [t.p1, t.p2, t.p3].iter().zip(n) {
+                assert!((p.magnitude() - 1.).abs() < 0.1, "{:?}", p);
+                assert!(n.dot(p) > 0.9, "{:?} at {:?}", n, p);
+            }
+        }
+    }
+}
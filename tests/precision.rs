@@ -0,0 +1,86 @@
+#[macro_use] extern crate rusterize;
+
+use rusterize::object::Object;
+use rusterize::renderer::Renderer;
+use rusterize::screen::TextScreen;
+use rusterize::types::*;
+
+// Results here are checked against values from an f64 build, loosely
+// enough that the f32 build has to match them too.
+
+fn close_to(a: Coord, expected: Coord) -> bool {
+    (a - expected).abs() <= 1e-4 * expected.abs().max(1.)
+}
+
+fn point_close_to(p: Point, expected: [Coord; 3]) -> bool {
+    close_to(p.x, expected[0]) && close_to(p.y, expected[1])
+        && close_to(p.z, expected[2])
+}
+
+fn screen_transform() -> Transform {
+    Transform::translate(pt![20., 15., 0.])
+        * Transform::scale(15., 15., 1.)
+        * Transform::perspective()
+}
+
+fn model_transform() -> Transform {
+    Transform::translate(pt![0.3, -0.2, -4.])
+        * Transform::rotate_y(0.7)
+        * Transform::scale(1.5, 0.5, 2.)
+}
+
+#[test]
+fn transforms_match_f64() {
+    let t = screen_transform() * model_transform();
+    let cases = [
+        (pt![1., 2., 3.], [231.2222583, 46.8071716, 1.]),
+        (pt![-0.5, 0.25, 1.], [27.6602360, 14.4338632, 1.]),
+        (pt![0., 0., 0.], [21.125, 14.25, 1.]),
+    ];
+    for &(p, expected) in &cases {
+        assert!(point_close_to(p * t, expected), "{:?}", p * t);
+    }
+
+    let model = model_transform();
+    let p = pt![3., -1., 2.];
+    assert!(point_close_to(p * model, [6.3186606, -0.7, -3.8396108]));
+    let back = p * model.inverse().unwrap();
+    assert!(point_close_to(back, [-1.2001548, -1.6, 3.1642204]));
+}
+
+#[test]
+fn intersections_match_f64() {
+    let triangle = trigon![
+        pt![0., 0., 0.], pt![3., 0.1, 0.], pt![0.2, 2., 0.5]
+    ];
+    let ray = Ray::new(pt![0.6, 0.5, 5.], pt![0.01, 0.02, -1.]);
+    let hit = triangle.intersect_ray(ray).unwrap();
+    assert!(close_to(hit.t, 4.8556452));
+    assert!(close_to(hit.u, 0.1969382) && close_to(hit.v, 0.2887095));
+}
+
+#[test]
+fn renders_match_f64() {
+    // Both windings, so it's drawn whichever way is front.
+    let (a, b, c) = (pt![-1., -1., -3.], pt![1., -0.8, -2.5], pt![0., 1., -2.]);
+    let object = Object::new(vec![trigon![a, b, c], trigon![a, c, b]]);
+    let mut renderer: Renderer<TextScreen> =
+        Renderer::new(TextScreen::new("", 40, 30));
+    renderer.set_transform(screen_transform());
+    object.render(&mut renderer);
+
+    let depths = renderer.depth_buffer();
+    let (mut covered, mut total) = (0, 0.);
+    for y in 0 .. 30 {
+        for x in 0 .. 40 {
+            let z = depths.get(x, y);
+            if z.is_finite() {
+                covered += 1;
+                total += z;
+            }
+        }
+    }
+    assert_eq!(covered, 88);
+    assert!(close_to(total, 219.98));
+    assert!(close_to(depths.get(20, 15), 2.45));
+}
@@ -0,0 +1,412 @@
+#[macro_use] extern crate rusterize;
+
+use rusterize::animation::Pose;
+use rusterize::antialias::Antialiasing;
+use rusterize::gbuffer::NO_ID;
+use rusterize::object::Object;
+use rusterize::pixel::BlendMode;
+use rusterize::pixel::Color;
+use rusterize::pixel::Rgba;
+use rusterize::renderer::FogMode;
+use rusterize::renderer::LightingMode;
+use rusterize::renderer::Renderer;
+use rusterize::renderer::back_to_front;
+use rusterize::scene::NodeContent;
+use rusterize::scene::Scene;
+use rusterize::screen::TextScreen;
+use rusterize::types::*;
+
+
+const W: Dimension = 40;
+const H: Dimension = 30;
+
+// Maps view space onto the screen, looking down -z.
+fn screen_transform() -> Transform {
+    let s = H as Coord / 2.;
+    Transform::translate(pt_2d![W as Coord / 2., H as Coord / 2.])
+        * Transform::scale(s, s, 1.)
+        * Transform::perspective()
+}
+
+fn renderer() -> Renderer<TextScreen> {
+    let mut renderer = Renderer::new(TextScreen::new("", W, H));
+    renderer.set_transform(screen_transform());
+    renderer
+}
+
+// A triangle facing the eye, covering the middle of the screen.
+fn facing_triangle(z: Coord) -> Triangle {
+    trigon![pt![-1., -1., z], pt![1., -1., z], pt![0., 1., z]]
+}
+
+fn close(a: Point, b: Point) -> bool { (a - b).magnitude() < 1e-4 }
+
+fn close_color(a: Color, b: Color) -> bool {
+    (a.r - b.r).abs() < 1e-4 && (a.g - b.g).abs() < 1e-4
+        && (a.b - b.b).abs() < 1e-4
+}
+
+
+#[test]
+fn gbuffer_normals_are_in_view_space() {
+    let triangle = facing_triangle(-2.);
+    let object = Object::new(vec![triangle]);
+    let view = Transform::rotate_y(0.15);
+
+    let mut renderer = renderer();
+    renderer.enable_gbuffer();
+    object.render_in(&mut renderer, Transform::identity(), view);
+    let gbuffer = renderer.gbuffer().unwrap();
+    let center = (H / 2 * W + W / 2) as usize;
+    assert_eq!(gbuffer.object_ids[center], object.id());
+    assert!(close(gbuffer.normals[center], (triangle * view).normal()));
+    assert!(!close(gbuffer.normals[center], triangle.normal()));
+}
+
+#[test]
+fn points_clear_what_pick_sees() {
+    let object = Object::new(vec![facing_triangle(-2.)]);
+    for &antialiasing in &[Antialiasing::None, Antialiasing::Multisample(4)] {
+        let mut renderer = renderer();
+        renderer.set_antialiasing(antialiasing);
+        renderer.enable_gbuffer();
+        object.render(&mut renderer);
+        renderer.resolve();
+        let (x, y) = (W / 2, H / 2);
+        assert_eq!(renderer.pick(x, y).unwrap().object, object.id());
+
+        // A point over the triangle's middle.
+        renderer.draw_point(pt![0., 0., -1.]);
+        renderer.resolve();
+        let center = (y * W + x) as usize;
+        assert_eq!(renderer.gbuffer().unwrap().object_ids[center], NO_ID);
+        assert!(renderer.pick(x, y).is_none_or(|p| p.object == NO_ID));
+    }
+}
+
+#[test]
+fn objects_are_drawn_without_perspective() {
+    // Both windings, so one faces the eye whichever way is front.
+    let (a, b, c) = (pt![5., 5., -1.], pt![30., 5., -1.], pt![15., 25., -1.]);
+    let object = Object::new(vec![trigon![a, b, c], trigon![a, c, b]]);
+
+    let screen = TextScreen::new("", W, H);
+    let mut renderer: Renderer<TextScreen> = Renderer::new(screen);
+    assert!(renderer.is_visible(&object.bounding_sphere(), &object.bounds()));
+    object.render(&mut renderer);
+    let depths = renderer.depth_buffer();
+    assert!(depths.get(15, 10).is_finite());
+}
+
+#[test]
+fn culling_follows_transform_and_near_clip_changes() {
+    let object = Object::new(vec![facing_triangle(-2.)]);
+    let (sphere, bounds) = (object.bounding_sphere(), object.bounds());
+    let mut renderer = renderer();
+    assert!(renderer.is_visible(&sphere, &bounds));
+    renderer.translate(pt![1000., 0., 0.]);
+    assert!(!renderer.is_visible(&sphere, &bounds));
+    renderer.set_transform(screen_transform());
+    assert!(renderer.is_visible(&sphere, &bounds));
+    renderer.set_near_clip(10.);
+    assert!(!renderer.is_visible(&sphere, &bounds));
+}
+
+#[test]
+fn rays_through_pixels_hit_what_the_gbuffer_holds() {
+    let (a, b) = (pt![-1., -1., 0.], pt![1., -1., 0.]);
+    let (c, d) = (pt![1., 1., 0.], pt![-1., 1., 0.]);
+    let quad = vec![
+        trigon![a, b, c], trigon![a, c, d],
+        trigon![a, c, b], trigon![a, d, c],
+    ];
+    // Moved after the hierarchy is built, so it has to be refit.
+    let mut object = Object::new(quad).with_bvh();
+    object.rotate_y(0.3);
+    object.translate(pt![0.2, 0.1, -3.]);
+
+    let mut renderer = renderer();
+    renderer.enable_gbuffer();
+    object.render(&mut renderer);
+
+    // Pixels inside the quad, away from its edges.
+    let covered: Vec<bool> = (0 .. W * H)
+        .map(|i| renderer.pick(i % W, i / W).is_some())
+        .collect();
+    let inside = |x: Dimension, y: Dimension| {
+        let at = |x: Dimension, y: Dimension| covered[(y * W + x) as usize];
+        at(x, y) && at(x - 1, y) && at(x + 1, y) && at(x, y - 1)
+            && at(x, y + 1)
+    };
+    let mut checked = 0;
+    for y in 1 .. H - 1 {
+        for x in 1 .. W - 1 {
+            if !inside(x, y) { continue }
+            let ray = renderer.ray_through(x, y).unwrap();
+            let hit = object.intersect_ray(ray).unwrap();
+            let picked = renderer.pick(x, y).unwrap().position;
+            assert!((hit.position - picked).magnitude() < 1e-3);
+            checked += 1;
+        }
+    }
+    assert!(checked > 20);
+}
+
+#[test]
+fn scenes_are_lit_by_all_their_lights_in_their_colors() {
+    let mut scene = Scene::new();
+    let object = Object::new(vec![facing_triangle(-2.)]);
+    scene.add("mesh", NodeContent::Mesh(Box::new(object)), None,
+              Pose::identity());
+    let red = Color::new(1., 0., 0.);
+    let blue = Color::new(0., 0., 2.);
+    scene.add("red", NodeContent::Light(red), None, Pose::identity());
+    scene.add("blue", NodeContent::Light(blue), None, Pose::identity());
+
+    let mut renderer = renderer();
+    renderer.set_lighting_mode(LightingMode::FlatShading);
+    renderer.render_scene(&scene);
+    let c = renderer.texture().pixels[(H / 2 * W + W / 2) as usize];
+    assert!(c.r > 0. && c.g == 0., "{:?}", c);
+    assert!((c.b - 2. * c.r).abs() < 1e-4, "{:?}", c);
+}
+
+#[test]
+fn multisampled_gbuffer_skips_uncovered_pixels() {
+    let object = Object::new(vec![facing_triangle(-2.)]);
+    let mut renderer = renderer();
+    renderer.set_antialiasing(Antialiasing::Multisample(4));
+    renderer.enable_gbuffer();
+    object.render(&mut renderer);
+    renderer.resolve();
+
+    // Every pixel the g-buffer claims for the object has some coverage.
+    let texture = renderer.texture();
+    let gbuffer = texture.gbuffer.as_ref().unwrap();
+    let depths = texture.depth();
+    let mut claimed = 0;
+    for y in 0 .. H {
+        for x in 0 .. W {
+            if gbuffer.object_ids[(y * W + x) as usize] == object.id() {
+                assert!(depths.get(x, y).is_finite(), "({}, {})", x, y);
+                claimed += 1;
+            }
+        }
+    }
+    assert!(claimed > 0);
+}
+
+#[test]
+fn multisampled_shared_edges_are_blended_once() {
+    // A square split along its diagonal, which samples lie exactly on.
+    let (a, b) = (pt![2., 2., -1.], pt![20., 2., -1.]);
+    let (c, d) = (pt![20., 20., -1.], pt![2., 20., -1.]);
+    let mut renderer: Renderer<TextScreen> =
+        Renderer::new(TextScreen::new("", W, H));
+    renderer.set_antialiasing(Antialiasing::Multisample(2));
+    renderer.set_blend_mode(BlendMode::Alpha);
+    renderer.set_depth_write(false);
+    renderer.set_alpha(0.5);
+    renderer.fill_triangle(trigon![a, b, c]);
+    renderer.fill_triangle(trigon![a, c, d]);
+    renderer.resolve();
+
+    let texture = renderer.texture();
+    let expected = texture.pixels[(5 * W + 10) as usize];
+    assert!(expected.r > 0., "{:?}", expected);
+    for y in 2 .. 20 {
+        for x in 2 .. 20 {
+            let pixel = texture.pixels[(y * W + x) as usize];
+            assert_eq!(pixel, expected, "({}, {})", x, y);
+        }
+    }
+}
+
+// Draws the same triangles opaque with fog, then with each blend mode and
+// depth writes off, into a screen several tiles across.
+fn draw_with_threads(threads: usize) -> Renderer<TextScreen> {
+    let (w, h) = (150, 100);
+    let mut renderer: Renderer<TextScreen> =
+        Renderer::new(TextScreen::new("", w, h));
+    let s = h as Coord / 2.;
+    renderer.set_transform(
+        Transform::translate(pt_2d![w as Coord / 2., h as Coord / 2.])
+            * Transform::scale(s, s, 1.)
+            * Transform::perspective()
+    );
+    renderer.enable_gbuffer();
+    renderer.set_threads(threads);
+    renderer.set_fog_mode(FogMode::Linear { start: 2., end: 9. });
+    renderer.set_fog_color(Color::new(0.2, 0.3, 0.4));
+
+    // A fixed pseudo-random sequence, the same for every call.
+    let mut seed: u32 = 12345;
+    let mut random = move || {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        (seed >> 8) as Coord / (1 << 24) as Coord
+    };
+    let mut point = || {
+        let z = -2. - random() * 6.;
+        pt![(random() * 3. - 1.5) * -z / 2., (random() * 2. - 1.) * -z / 2., z]
+    };
+    let modes = [BlendMode::Replace, BlendMode::Alpha, BlendMode::Additive,
+                 BlendMode::Multiply];
+    for (i, &mode) in modes.iter().enumerate() {
+        renderer.set_blend_mode(mode);
+        renderer.set_depth_write(mode == BlendMode::Replace);
+        renderer.set_alpha(if mode == BlendMode::Replace { 1. } else { 0.5 });
+        for j in 0 .. 40 {
+            let (a, b, c) = (point(), point(), point());
+            let k = (i * 40 + j) as f32 / 160.;
+            renderer.set_color(Color::new(k, 1. - k, 0.5));
+            renderer.set_object_id((i * 40 + j) as u32);
+            // Both windings, so it's drawn either way round.
+            renderer.fill_triangle(trigon![a, b, c]);
+            renderer.fill_triangle(trigon![a, c, b]);
+        }
+    }
+    renderer.resolve();
+    renderer
+}
+
+#[test]
+fn threads_draw_the_same_image() {
+    let (mut one, mut four) = (draw_with_threads(1), draw_with_threads(4));
+    let (one, four) = (one.texture(), four.texture());
+    assert!(one.pixels == four.pixels);
+
+    let a = one.gbuffer.as_ref().unwrap();
+    let b = four.gbuffer.as_ref().unwrap();
+    assert_eq!(a.object_ids, b.object_ids);
+    assert_eq!(a.triangle_indices, b.triangle_indices);
+    let mut covered = 0;
+    for y in 0 .. a.h {
+        for x in 0 .. a.w {
+            let i = (y * a.w + x) as usize;
+            let (z1, z2) = (one.depth().get(x, y), four.depth().get(x, y));
+            assert!(z1 == z2 || z1.is_infinite() && z2.is_infinite());
+            if z1.is_finite() { covered += 1 }
+            assert!(close(a.normals[i], b.normals[i]));
+            assert!(close(a.positions[i], b.positions[i]));
+        }
+    }
+    assert!(covered > a.w * a.h / 10);
+}
+
+#[test]
+fn threaded_drawing_is_read_back_without_resolving() {
+    let object = Object::new(vec![facing_triangle(-2.)]);
+    let mut renderer = renderer();
+    renderer.enable_gbuffer();
+    renderer.set_threads(2);
+    object.render(&mut renderer);
+    assert!(renderer.depth_buffer().get(W / 2, H / 2).is_finite());
+    assert_eq!(renderer.pick(W / 2, H / 2).unwrap().object, object.id());
+}
+
+#[test]
+fn blend_modes_combine_as_documented() {
+    let src = Rgba::new(0.8, 0.4, 0.2, 0.5);
+    let dst = Color::new(0.2, 0.6, 1.);
+    let cases = [
+        (BlendMode::Replace, Color::new(0.8, 0.4, 0.2)),
+        (BlendMode::Alpha, Color::new(0.5, 0.5, 0.6)),
+        (BlendMode::Additive, Color::new(0.6, 0.8, 1.1)),
+        (BlendMode::Multiply, Color::new(0.18, 0.42, 0.6)),
+    ];
+    for &(mode, expected) in &cases {
+        let blended = mode.blend(src, dst);
+        assert!(close_color(blended, expected), "{:?}: {:?}", mode, blended);
+    }
+    // Premultiplied colors come out as Alpha does for straight ones.
+    let premultiplied =
+        BlendMode::Premultiplied.blend(src.premultiplied(), dst);
+    assert!(close_color(premultiplied, BlendMode::Alpha.blend(src, dst)));
+
+    // Alpha and Premultiplied build up coverage; the others keep it.
+    let half = Rgba::from_color(dst, 0.5);
+    assert_eq!(BlendMode::Alpha.blend_rgba(src, half).a, 0.75);
+    assert_eq!(BlendMode::Additive.blend_rgba(src, half).a, 0.5);
+    assert_eq!(BlendMode::Replace.blend_rgba(src, half).a, 0.5);
+}
+
+#[test]
+fn translucent_triangles_blend_over_what_is_behind() {
+    let mut renderer = renderer();
+    renderer.set_color(Color::new(1., 0., 0.));
+    renderer.fill_triangle(facing_triangle(-3.));
+
+    renderer.set_blend_mode(BlendMode::Alpha);
+    renderer.set_depth_write(false);
+    renderer.set_alpha(0.5);
+    renderer.set_color(Color::new(0., 0., 1.));
+    renderer.fill_triangle(facing_triangle(-2.));
+    // Hidden behind the opaque one.
+    renderer.set_blend_mode(BlendMode::Additive);
+    renderer.set_color(Color::new(0., 1., 0.));
+    renderer.fill_triangle(facing_triangle(-4.));
+
+    let (x, y) = (W / 2, H / 2);
+    let texture = renderer.texture();
+    let pixel = texture.pixels[(y * W + x) as usize];
+    assert!(close_color(pixel, Color::new(0.5, 0., 0.5)), "{:?}", pixel);
+    // The opaque triangle's depth is kept.
+    assert!((texture.depth().get(x, y) - 3.).abs() < 1e-4);
+
+    let triangles = [facing_triangle(-2.), facing_triangle(-5.),
+                     facing_triangle(-3.)];
+    assert_eq!(back_to_front(&triangles), vec![1, 2, 0]);
+}
+
+// Draws an opaque gray triangle, then translucent ones in the given order,
+// and returns the middle pixel once resolved.
+fn draw_layers(order: &[usize], max_fragments: usize) -> Color {
+    // Depths and colors, with one behind the opaque triangle.
+    let layers = [
+        (-2., Color::new(1., 0., 0.), 0.5),
+        (-3., Color::new(0., 1., 0.), 0.25),
+        (-4., Color::new(0., 0., 1.), 0.75),
+        (-6., Color::new(1., 1., 1.), 1.),
+    ];
+    let mut renderer = renderer();
+    renderer.enable_abuffer(max_fragments);
+    renderer.set_color(Color::new(0.2, 0.2, 0.2));
+    renderer.fill_triangle(facing_triangle(-5.));
+
+    renderer.set_blend_mode(BlendMode::Alpha);
+    renderer.set_depth_write(false);
+    for &i in order {
+        let (z, color, alpha) = layers[i];
+        renderer.set_color(color);
+        renderer.set_alpha(alpha);
+        renderer.fill_triangle(facing_triangle(z));
+    }
+    renderer.resolve();
+    renderer.texture().pixels[(H / 2 * W + W / 2) as usize]
+}
+
+#[test]
+fn abuffer_blends_the_same_in_any_order() {
+    // Blue, then green, then red over the gray.
+    let over = |dst: Color, src: Color, a: f32| src * a + dst * (1. - a);
+    let gray = Color::new(0.2, 0.2, 0.2);
+    let blue = over(gray, Color::new(0., 0., 1.), 0.75);
+    let green = over(blue, Color::new(0., 1., 0.), 0.25);
+    let expected = over(green, Color::new(1., 0., 0.), 0.5);
+
+    let orders = [
+        [0, 1, 2, 3], [3, 2, 1, 0], [1, 3, 0, 2], [2, 0, 3, 1], [1, 0, 2, 3],
+    ];
+    for order in &orders {
+        let pixel = draw_layers(order, 8);
+        assert!(close_color(pixel, expected), "{:?}: {:?}", order, pixel);
+    }
+
+    // Short of room, the farthest fragments are dropped, in any order.
+    let green = over(gray, Color::new(0., 1., 0.), 0.25);
+    let expected = over(green, Color::new(1., 0., 0.), 0.5);
+    for order in &orders {
+        let pixel = draw_layers(order, 2);
+        assert!(close_color(pixel, expected), "{:?}: {:?}", order, pixel);
+    }
+}
@@ -0,0 +1,70 @@
+extern crate rusterize;
+
+use std::path::Path;
+
+use rusterize::scenefile::NodeKind;
+use rusterize::scenefile::SceneFile;
+
+
+const SCENE: &str = r#"background 0 0 32
+
+[group]
+name "two  spaces # and a hash"
+
+[object]
+name plain
+parent "two  spaces # and a hash"  # A comment.
+mesh "meshes/a \"quoted\" \\ path #2.obj"
+translation 1 2 3
+color 255 128 0
+
+[light]
+name lamp
+color 255 240 200
+intensity 2.5
+"#;
+
+fn write(file: &SceneFile) -> String {
+    let mut out = Vec::new();
+    file.write(&mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn quoted_strings_keep_whitespace_and_hashes() {
+    let file = SceneFile::parse(SCENE).unwrap();
+    let name = "two  spaces # and a hash";
+    assert_eq!(file.nodes[0].name, name);
+    assert_eq!(file.nodes[1].parent.as_ref().unwrap(), name);
+    match file.nodes[1].kind {
+        NodeKind::Object { ref mesh, .. } => {
+            assert_eq!(mesh, "meshes/a \"quoted\" \\ path #2.obj");
+        },
+        ref kind => panic!("expected an object, found {:?}", kind),
+    }
+}
+
+#[test]
+fn save_load_save_round_trips() {
+    let saved = write(&SceneFile::parse(SCENE).unwrap());
+    let loaded = SceneFile::parse(&saved).unwrap();
+    assert_eq!(write(&loaded), saved);
+    assert_eq!(loaded.nodes[0].name, "two  spaces # and a hash");
+    assert_eq!(loaded.nodes[2].kind,
+               NodeKind::Light { color: (255, 240, 200), intensity: 2.5 });
+}
+
+#[test]
+fn unquoted_strings_with_spaces_are_rejected() {
+    let error = SceneFile::parse("[group]\nname two words\n").unwrap_err();
+    assert!(error.to_string().starts_with("2: "), "{}", error);
+    assert!(SceneFile::parse("[group]\nname \"open\n").is_err());
+}
+
+#[test]
+fn mesh_errors_have_line_numbers() {
+    let text = "[group]\nname g\n\n[object]\nname o\nmesh missing.obj\n";
+    let file = SceneFile::parse(text).unwrap();
+    let error = file.build(Path::new("/nonexistent")).err().unwrap();
+    assert!(error.to_string().starts_with("4: "), "{}", error);
+}